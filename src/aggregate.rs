@@ -0,0 +1,230 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// what is shown at most, the rest is counted in a summary diagnostic.
+// hints, like never executed lines, don't count
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub per_file: usize,
+    pub total: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            per_file: 200,
+            total: 2000,
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn same_report(a: &Value, b: &Value) -> bool {
+    a["range"] == b["range"]
+        && a["severity"] == b["severity"]
+        && a["source"] == b["source"]
+        && a["message"] == b["message"]
+}
+
+fn count(d: &Value) -> u64 {
+    d["data"]["count"].as_u64().unwrap_or(1)
+}
+
+fn is_capped(d: &Value) -> bool {
+    d["severity"].as_u64().is_none_or(|s| s < 4) && d["data"]["summary"] != json!(true)
+}
+
+// a report read again, ex. after the config changed, keeps the times it was first read
+pub fn keep_times(old: &[Value], diag: &mut Value) {
+    if diag["data"]["firstSeen"].is_u64() {
+        return;
+    }
+    if let Some(d) = old.iter().find(|d| same_report(d, diag)) {
+        diag["data"]["firstSeen"] = d["data"]["firstSeen"].clone();
+        diag["data"]["lastSeen"] = d["data"]["lastSeen"].clone();
+    }
+}
+
+// another occurrence of a report already in `list` only counts, ex. the same traceback
+// printed by a loop. a new report is stamped with `now` unless it was seen before
+pub fn add(list: &mut Vec<Value>, mut diag: Value, now: u64) {
+    if !diag["data"]["firstSeen"].is_u64() {
+        diag["data"]["firstSeen"] = json!(now);
+        diag["data"]["lastSeen"] = json!(now);
+    }
+    let Some(d) = list.iter_mut().find(|d| same_report(d, &diag)) else {
+        list.push(diag);
+        return;
+    };
+    let first = d["data"]["firstSeen"].as_u64().unwrap_or(now);
+    let last = d["data"]["lastSeen"].as_u64().unwrap_or(now);
+    d["data"]["count"] = json!(count(d) + count(&diag));
+    d["data"]["firstSeen"] = json!(first.min(diag["data"]["firstSeen"].as_u64().unwrap_or(now)));
+    d["data"]["lastSeen"] = json!(last.max(diag["data"]["lastSeen"].as_u64().unwrap_or(now)));
+}
+
+// keeps the `budget` most severe reports, returns how many were dropped
+pub fn cap(list: &mut Vec<Value>, budget: usize) -> usize {
+    let capped = list.iter().filter(|d| is_capped(d)).count();
+    if capped <= budget {
+        return 0;
+    }
+    list.sort_by_key(|d| d["severity"].as_u64().unwrap_or(1));
+    let mut kept = 0;
+    list.retain(|d| {
+        if !is_capped(d) {
+            return true;
+        }
+        kept += 1;
+        kept <= budget
+    });
+    capped - budget
+}
+
+pub fn capped_len(list: &[Value]) -> usize {
+    list.iter().filter(|d| is_capped(d)).count()
+}
+
+// how many reports each file shows. a file which has less than an even share of the total
+// leaves the rest to the others, `counts` are the capped reports of every file
+pub fn budgets(counts: &HashMap<String, usize>, limits: &Limits) -> HashMap<String, usize> {
+    let mut files: Vec<(&String, usize)> = counts
+        .iter()
+        .map(|(uri, n)| (uri, (*n).min(limits.per_file)))
+        .collect();
+    files.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(b.0)));
+    let mut left = limits.total;
+    let n = files.len();
+    files
+        .into_iter()
+        .enumerate()
+        .map(|(i, (uri, wanted))| {
+            let budget = wanted.min(left / (n - i));
+            left -= budget;
+            (uri.clone(), budget)
+        })
+        .collect()
+}
+
+pub fn summary(dropped: usize, limits: &Limits) -> Value {
+    json!({
+        "range": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 0 }
+        },
+        "severity": 3,
+        "source": "clasangd",
+        "message": format!(
+            "{} more diagnostics not shown, at most {} per file and {} in total",
+            dropped, limits.per_file, limits.total
+        ),
+        "data": { "summary": true }
+    })
+}
+
+// local HH:MM:SS
+fn clock(secs: u64) -> String {
+    let t = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&t, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+// the diagnostic as published, a repeated report says how often and when
+pub fn describe(d: &Value) -> Value {
+    let n = count(d);
+    if n < 2 {
+        return d.clone();
+    }
+    let mut d = d.clone();
+    let (first, last) = (
+        d["data"]["firstSeen"].as_u64().unwrap_or(0),
+        d["data"]["lastSeen"].as_u64().unwrap_or(0),
+    );
+    let times = if first == last {
+        format!("at {}", clock(first))
+    } else {
+        format!("first {}, last {}", clock(first), clock(last))
+    };
+    if let Some(msg) = d["message"].as_str() {
+        d["message"] = json!(format!("{} ({} times, {})", msg, n, times));
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diag(line: u64, severity: u64, message: &str) -> Value {
+        json!({
+            "range": {
+                "start": { "line": line, "character": 0 },
+                "end": { "line": line, "character": 1 }
+            },
+            "severity": severity,
+            "source": "runtime",
+            "message": message
+        })
+    }
+
+    #[test]
+    fn folds_repeats_and_caps() {
+        let mut list = Vec::new();
+        for t in 0..1000 {
+            add(&mut list, diag(4, 1, "ZeroDivisionError"), 100 + t);
+        }
+        add(&mut list, diag(5, 2, "ZeroDivisionError"), 100);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0]["data"]["count"], json!(1000));
+        assert_eq!(list[0]["data"]["firstSeen"], json!(100));
+        assert_eq!(list[0]["data"]["lastSeen"], json!(1099));
+        let shown = describe(&list[0]);
+        assert!(
+            shown["message"]
+                .as_str()
+                .unwrap()
+                .starts_with("ZeroDivisionError (1000 times, first "),
+            "{}",
+            shown["message"]
+        );
+        assert_eq!(describe(&list[1])["message"], json!("ZeroDivisionError"));
+        // read again, the report keeps its times
+        let mut again = diag(4, 1, "ZeroDivisionError");
+        keep_times(&list, &mut again);
+        assert_eq!(again["data"]["firstSeen"], json!(100));
+        assert_eq!(again["data"]["lastSeen"], json!(1099));
+
+        let mut list: Vec<Value> = (0..10).map(|l| diag(l, 2 - l % 2, "boom")).collect();
+        list.push(diag(20, 4, "never executed"));
+        assert_eq!(cap(&mut list, 3), 7);
+        assert_eq!(list.len(), 4);
+        assert!(list[..3].iter().all(|d| d["severity"] == json!(1)));
+        list.push(summary(7, &Limits::default()));
+        assert_eq!(capped_len(&list), 3);
+
+        // the small file keeps all, the others split what is left
+        let limits = Limits {
+            per_file: 200,
+            total: 100,
+        };
+        let counts = HashMap::from([
+            ("a".to_string(), 10),
+            ("b".to_string(), 500),
+            ("c".to_string(), 80),
+        ]);
+        let budgets = budgets(&counts, &limits);
+        assert_eq!(budgets["a"], 10);
+        assert_eq!(budgets["b"] + budgets["c"], 90);
+        assert_eq!(budgets["c"], 45);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::InputDiags;
+use crate::edit;
+use crate::fingerprint;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+// restored diagnostics are kept under this prefix until their input is read again
+pub const RESTORED: &str = "restored:";
+
+// the diagnostics of the last session of a workspace
+#[derive(Serialize, Deserialize, Default)]
+struct Cache {
+    // input -> uri -> diagnostics
+    inputs: BTreeMap<String, HashMap<String, Vec<Value>>>,
+    // uri -> hash of the file when the diagnostics were saved
+    files: HashMap<String, String>,
+}
+
+// FNV-1a, stable across builds unlike DefaultHasher
+pub fn hash(bytes: &[u8]) -> String {
+    let h = bytes.iter().fold(0xcbf29ce484222325u64, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", h)
+}
+
+fn file_hash(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix("file://")?;
+    std::fs::read(path).ok().map(|b| hash(&b))
+}
+
+// $XDG_CACHE_HOME/clasangd, or ~/.cache/clasangd
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".cache")))?;
+    Some(base.join("clasangd"))
+}
+
+// <cache dir>/<hash of the root>.json
+pub fn cache_path(dir: &Path, root_path: &str) -> Option<PathBuf> {
+    if root_path.is_empty() {
+        return None;
+    }
+    Some(dir.join(format!("{}.json", hash(root_path.as_bytes()))))
+}
+
+pub fn save(path: &Path, inputs: &BTreeMap<String, InputDiags>) -> Result<()> {
+    let mut cache = Cache::default();
+    for (input, diags) in inputs {
+        let input = input.strip_prefix(RESTORED).unwrap_or(input);
+        let saved = cache.inputs.entry(input.to_string()).or_default();
+        for (uri, list) in diags.committed.iter().chain(&diags.provisional) {
+            if list.is_empty() {
+                continue;
+            }
+            saved
+                .entry(uri.clone())
+                .or_default()
+                .extend(list.iter().cloned());
+            if !cache.files.contains_key(uri)
+                && let Some(h) = file_hash(uri)
+            {
+                cache.files.insert(uri.clone(), h);
+            }
+        }
+    }
+    cache.inputs.retain(|_, diags| !diags.is_empty());
+
+    // the messages come from logs only the user may read
+    if let Some(dir) = path.parent() {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    let bytes = serde_json::to_vec(&cache)?;
+    let tmp = path.with_extension("json.tmp");
+    let _ = std::fs::remove_file(&tmp);
+    OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut f| f.write_all(&bytes))
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to write {}", path.display()))
+}
+
+// the saved diagnostics, checked against the files as they are now. a file which changed
+// since has its diagnostics relocated by fingerprint, or marked stale
+pub fn load(path: &Path) -> Result<BTreeMap<String, HashMap<String, Vec<Value>>>> {
+    let txt = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut cache: Cache = serde_json::from_slice(&txt)
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+    let changed: Vec<String> = cache
+        .files
+        .iter()
+        .filter(|(uri, h)| file_hash(uri).as_ref() != Some(*h))
+        .map(|(uri, _)| uri.clone())
+        .collect();
+    for diags in cache.inputs.values_mut() {
+        for uri in &changed {
+            let Some(list) = diags.remove(uri) else {
+                continue;
+            };
+            let mut moved = HashMap::from([(uri.clone(), list)]);
+            fingerprint::relocate(&mut moved);
+            for d in moved.values_mut().flatten() {
+                if !d["data"]["fingerprint"].is_string() {
+                    edit::mark_stale(d);
+                }
+            }
+            diags.extend(moved);
+        }
+    }
+    Ok(cache.inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn restores_and_marks_changed_files_stale() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.c");
+        std::fs::write(&file_path, "int main(void) {\n    return 1 / 0;\n}\n").unwrap();
+        let uri = format!("file://{}", file_path.display());
+        let diag = json!({
+            "range": {
+                "start": { "line": 1, "character": 13 },
+                "end": { "line": 1, "character": 14 }
+            },
+            "severity": 2,
+            "source": "compiler",
+            "message": "division by zero"
+        });
+        let mut inputs = BTreeMap::new();
+        inputs.insert(
+            "/tmp/clasangd_build.log".to_string(),
+            InputDiags {
+                committed: HashMap::from([(uri.clone(), vec![diag.clone()])]),
+                provisional: HashMap::new(),
+            },
+        );
+        let path = temp_dir.path().join("cache.json");
+        save(&path, &inputs).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let loaded = load(&path).unwrap();
+        assert_eq!(loaded["/tmp/clasangd_build.log"][&uri], vec![diag]);
+
+        std::fs::write(&file_path, "int main(void) {\n    return 0;\n}\n").unwrap();
+        let loaded = load(&path).unwrap();
+        let restored = &loaded["/tmp/clasangd_build.log"][&uri][0];
+        assert_eq!(restored["message"], json!("[stale] division by zero"));
+    }
+}
@@ -0,0 +1,330 @@
+use crate::IS_VERBOSE;
+use crate::errorformat::ErrorFormat;
+use crate::path_filter::PathFilter;
+use crate::remap::{self, Remap};
+use anyhow::{Context, Result, anyhow, bail};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const PROJECT_CONFIG: &str = ".clasangd.toml";
+
+// ex. .clasangd.toml
+// [[parser]]
+// name = "mytool"
+// regex = '^(?P<file>[^:]+):(?P<line>\d+): (?P<severity>[EW])\d+ (?P<message>.*)$'
+// severity = { E = "error", W = "warning" }
+// source = "mytool"
+//
+// [[parser]]
+// name = "make"
+// errorformat = '%f:%l:%c: %t%*[^:]: %m,%-G%.%#'
+//
+// [paths]
+// exclude = ["build/**", "/usr/**"]
+// just_my_code = true
+//
+// [[remap]]
+// from = "/src/app"
+// to = "."
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct Config {
+    #[serde(rename = "parser")]
+    pub parsers: Vec<ParserConfig>,
+    pub paths: PathsConfig,
+    #[serde(rename = "remap")]
+    pub remaps: Vec<RemapConfig>,
+}
+
+// a path prefix in the logs and where it is here, relative to the workspace root or absolute
+#[derive(Deserialize, Debug, Clone)]
+pub struct RemapConfig {
+    pub from: String,
+    pub to: String,
+}
+
+// which files diagnostics may point at, globs relative to a workspace root or absolute
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct PathsConfig {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    // only files under the workspace roots, outside vendored directories
+    pub just_my_code: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ParserConfig {
+    pub name: String,
+    pub regex: Option<String>,
+    // vim 'errorformat', either comma separated or a list
+    pub errorformat: Option<StringOrList>,
+    // multi-line block: regex runs on the lines from `start` up to `end` (or the next start / blank line)
+    pub start: Option<String>,
+    pub end: Option<String>,
+    // captured severity text -> "error" | "warning" | "information" | "hint" | 1..4
+    #[serde(default)]
+    pub severity: HashMap<String, String>,
+    pub default_severity: Option<String>,
+    pub source: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum StringOrList {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    Regex(Regex),
+    ErrorFormat(ErrorFormat),
+}
+
+#[derive(Debug, Clone)]
+pub struct UserParser {
+    pub name: String,
+    pub matcher: Matcher,
+    pub start: Option<Regex>,
+    pub end: Option<Regex>,
+    pub severity: HashMap<String, u64>,
+    pub default_severity: u64,
+    pub source: String,
+}
+
+impl UserParser {
+    pub fn compile(cfg: &ParserConfig) -> Result<Self> {
+        let multi_line = |re: &str| {
+            RegexBuilder::new(re)
+                .multi_line(true)
+                .build()
+                .with_context(|| format!("parser {}: invalid regex {}", cfg.name, re))
+        };
+        let matcher = match (&cfg.regex, &cfg.errorformat) {
+            (Some(_), Some(_)) => bail!("parser {}: set regex or errorformat, not both", cfg.name),
+            (None, Some(StringOrList::One(efm))) => {
+                Matcher::ErrorFormat(ErrorFormat::parse(std::slice::from_ref(efm))?)
+            }
+            (None, Some(StringOrList::Many(efm))) => Matcher::ErrorFormat(ErrorFormat::parse(efm)?),
+            (Some(re), None) => Matcher::Regex(multi_line(re)?),
+            (None, None) => bail!("parser {}: regex or errorformat is required", cfg.name),
+        };
+        let start = cfg.start.as_deref().map(multi_line).transpose()?;
+        let end = cfg.end.as_deref().map(multi_line).transpose()?;
+
+        let mut severity = HashMap::new();
+        for (captured, sev) in &cfg.severity {
+            let sev = severity_from_str(sev)
+                .ok_or_else(|| anyhow!("parser {}: unknown severity {}", cfg.name, sev))?;
+            severity.insert(captured.to_string(), sev);
+        }
+        let default_severity = match cfg.default_severity.as_deref() {
+            Some(sev) => severity_from_str(sev)
+                .ok_or_else(|| anyhow!("parser {}: unknown severity {}", cfg.name, sev))?,
+            None => 1,
+        };
+
+        Ok(UserParser {
+            name: cfg.name.clone(),
+            matcher,
+            start,
+            end,
+            severity,
+            default_severity,
+            source: cfg.source.clone().unwrap_or_else(|| cfg.name.clone()),
+        })
+    }
+
+    // the line starts a report of this parser, which may go on in the lines after it
+    pub fn starts_record(&self, line: &str) -> bool {
+        self.start.as_ref().is_some_and(|s| s.is_match(line))
+            || matches!(&self.matcher, Matcher::ErrorFormat(efm) if efm.starts_entry(line))
+    }
+
+    pub fn severity_of(&self, captured: Option<&str>) -> u64 {
+        let Some(captured) = captured else {
+            return self.default_severity;
+        };
+        self.severity
+            .get(captured)
+            .copied()
+            .or_else(|| severity_from_str(captured))
+            .unwrap_or(self.default_severity)
+    }
+}
+
+// 1=Error,2=Warning,3=Information,4=Hint
+pub fn severity_from_str(s: &str) -> Option<u64> {
+    match s.trim().to_ascii_lowercase().as_str() {
+        "1" | "error" | "fatal error" | "fatal" => Some(1),
+        "2" | "warning" | "warn" => Some(2),
+        "3" | "information" | "info" | "note" => Some(3),
+        "4" | "hint" => Some(4),
+        _ => None,
+    }
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".config")))?;
+    Some(base.join("clasangd").join("config.toml"))
+}
+
+// user config first, the project config is applied on top of it
+pub fn config_paths(user_config: Option<&Path>, root_path: &str) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = user_config.map(Path::to_path_buf).into_iter().collect();
+    if !root_path.is_empty() {
+        paths.push(Path::new(root_path).join(PROJECT_CONFIG));
+    }
+    paths
+}
+
+pub fn load_config(path: &Path) -> Result<Config> {
+    let txt = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&txt).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+// each config file that exists, read once; one which fails to parse is reported and skipped
+pub fn load_configs(paths: &[PathBuf]) -> Vec<(PathBuf, Config)> {
+    paths
+        .iter()
+        .filter(|p| p.is_file())
+        .filter_map(|path| match load_config(path) {
+            Ok(cfg) => Some((path.clone(), cfg)),
+            Err(e) => {
+                eprintln!("[clasangd] {:#}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+// broken parsers are reported and skipped
+pub fn user_parsers(configs: &[(PathBuf, Config)]) -> Vec<UserParser> {
+    let mut parsers = Vec::new();
+    for (path, cfg) in configs {
+        for p in &cfg.parsers {
+            match UserParser::compile(p) {
+                Ok(parser) => parsers.push(parser),
+                Err(e) => eprintln!("[clasangd] {}: {:#}", path.display(), e),
+            }
+        }
+        unsafe {
+            if 0 < IS_VERBOSE {
+                eprintln!(
+                    "[clasangd] Loaded {} parsers from {}",
+                    cfg.parsers.len(),
+                    path.display()
+                );
+            }
+        }
+    }
+    parsers
+}
+
+// the rules of every config file together, a later file can only turn just_my_code on
+pub fn path_filter(configs: &[(PathBuf, Config)], roots: &[String]) -> PathFilter {
+    let mut rules = PathsConfig::default();
+    for (_, cfg) in configs {
+        rules.include.extend(cfg.paths.include.iter().cloned());
+        rules.exclude.extend(cfg.paths.exclude.iter().cloned());
+        rules.just_my_code |= cfg.paths.just_my_code;
+    }
+    match PathFilter::new(&rules, roots) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("[clasangd] {:#}", e);
+            PathFilter::default()
+        }
+    }
+}
+
+// the remap rules of the config files, the project's first, then the prefix maps
+// of the compilation database
+pub fn remaps(configs: &[(PathBuf, Config)], root: &str) -> Vec<Remap> {
+    let mut rules: Vec<Remap> = configs
+        .iter()
+        .rev()
+        .flat_map(|(_, cfg)| cfg.remaps.iter().map(|r| Remap::new(&r.from, &r.to, root)))
+        .collect();
+    if !root.is_empty() {
+        rules.extend(remap::from_compile_commands(root));
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_parser;
+    use serde_json::json;
+
+    #[test]
+    fn user_parser_runs_in_parse_diagnostics() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("main.lua");
+        std::fs::write(&file_path, "-- lua test file").unwrap();
+
+        let cfg: Config = toml::from_str(
+            r#"
+            [[parser]]
+            name = "lint"
+            regex = '^(?P<file>\S+):(?P<line>\d+) (?P<severity>[EW])(?P<code>\d+) (?P<message>.*)$'
+            severity = { E = "error", W = "warning" }
+
+            [[parser]]
+            name = "panic"
+            start = '^PANIC$'
+            end = '^END$'
+            regex = 'at (?P<file>\S+) line (?P<line>\d+)\n(?P<message>.*)'
+            default_severity = "error"
+            source = "lua-runtime"
+            "#,
+        )
+        .unwrap();
+        let parsers: Vec<UserParser> = cfg
+            .parsers
+            .iter()
+            .map(|p| UserParser::compile(p).unwrap())
+            .collect();
+
+        let log = format!(
+            "{f}:3 W211 unused variable 'x'\nPANIC\nat {f} line 9\nattempt to index a nil value\nEND\n",
+            f = file_path.display()
+        );
+        let out = log_parser::parse_diagnostics(
+            &log,
+            "file:///tmp/dummy.lua",
+            temp_dir.path().to_str().unwrap(),
+            &parsers,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = out.get(&uri).expect("diagnostic missing for user parser");
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["severity"], json!(2));
+        assert_eq!(diags[0]["code"], json!("211"));
+        assert_eq!(diags[0]["source"], json!("lint"));
+        assert_eq!(diags[1]["range"]["start"]["line"], json!(8));
+        assert_eq!(diags[1]["source"], json!("lua-runtime"));
+        assert_eq!(diags[1]["message"], json!("attempt to index a nil value"));
+
+        let both: Config = toml::from_str(
+            r#"
+            [[parser]]
+            name = "both"
+            regex = '^(?P<file>\S+):(?P<line>\d+) (?P<message>.*)$'
+            errorformat = '%f:%l: %m'
+            "#,
+        )
+        .unwrap();
+        let err = UserParser::compile(&both.parsers[0]).unwrap_err();
+        assert!(format!("{:#}", err).contains("parser both"), "{:#}", err);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::log_parser::make_uri;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, HashMap};
+
+// hits of one source file in an lcov tracefile
+#[derive(Default)]
+struct Record {
+    lines: BTreeMap<u64, u64>,
+    // name -> (line, hits)
+    functions: BTreeMap<String, (u64, u64)>,
+}
+
+pub fn parse_lcov(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    diags: &mut HashMap<String, Vec<Value>>,
+    lenses: &mut HashMap<String, Vec<Value>>,
+) {
+    // ex.
+    // TN:
+    // SF:/home/moamoa/clasangd/test.c
+    // FN:3,main           ... or FN:3,15,main (lcov 2.x)
+    // FNDA:1,main
+    // DA:3,1
+    // DA:4,0
+    // end_of_record
+    let mut records: Vec<(String, Record)> = Vec::new();
+    let mut current: Option<(String, Record)> = None;
+
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(file) = line.strip_prefix("SF:") {
+            current = Some((file.to_string(), Record::default()));
+        } else if line == "end_of_record" {
+            records.extend(current.take());
+        } else if let Some((_, rec)) = current.as_mut() {
+            if let Some(da) = line.strip_prefix("DA:") {
+                let mut it = da.split(',');
+                if let (Some(l), Some(h)) = (it.next(), it.next())
+                    && let (Ok(l), Ok(h)) = (l.parse::<u64>(), h.parse::<u64>())
+                {
+                    *rec.lines.entry(l).or_default() += h;
+                }
+            } else if let Some(func) = line.strip_prefix("FN:") {
+                let parts: Vec<&str> = func.split(',').collect();
+                if let (Some(l), Some(name)) = (parts.first(), parts.last())
+                    && let Ok(l) = l.parse::<u64>()
+                    && parts.len() >= 2
+                {
+                    rec.functions.entry(name.to_string()).or_default().0 = l;
+                }
+            } else if let Some(fnda) = line.strip_prefix("FNDA:")
+                && let Some((h, name)) = fnda.split_once(',')
+                && let Ok(h) = h.parse::<u64>()
+            {
+                rec.functions.entry(name.to_string()).or_default().1 += h;
+            }
+        }
+    }
+    records.extend(current);
+
+    for (file, rec) in records {
+        let uri = make_uri(&file, saved_uri, root_path);
+
+        // consecutive never executed lines become one hint
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for (&l, &h) in &rec.lines {
+            if h != 0 {
+                continue;
+            }
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == l => *end = l,
+                _ => ranges.push((l, l)),
+            }
+        }
+        let file_diags = diags.entry(uri.clone()).or_default();
+        for (start, end) in ranges {
+            let message = if start == end {
+                "never executed".to_string()
+            } else {
+                format!("never executed ({} lines)", end - start + 1)
+            };
+            file_diags.push(json!({
+                "range": {
+                    "start": { "line": start.saturating_sub(1), "character": 0 },
+                    "end": { "line": end, "character": 0 }
+                },
+                "severity": 4,
+                "source": "coverage",
+                "message": message
+            }));
+        }
+
+        let file_lenses = lenses.entry(uri).or_default();
+        for (name, (l, h)) in &rec.functions {
+            if *l == 0 {
+                continue;
+            }
+            let l = l.saturating_sub(1);
+            let title = match h {
+                1 => format!("{} called 1 time", name),
+                _ => format!("{} called {} times", name, h),
+            };
+            file_lenses.push(json!({
+                "range": {
+                    "start": { "line": l, "character": 0 },
+                    "end": { "line": l, "character": 0 }
+                },
+                "command": { "title": title, "command": "" }
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lcov_tracefile() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.c");
+        std::fs::write(&file_path, "int main(void) { return 0; }").unwrap();
+
+        let lcov = format!(
+            "TN:\nSF:{}\nFN:3,main\nFN:10,14,unused\nFNDA:2,main\nFNDA:0,unused\n\
+             DA:3,2\nDA:4,0\nDA:10,0\nDA:11,0\nDA:12,0\nDA:13,1\nend_of_record\n",
+            file_path.display()
+        );
+        let mut diags = HashMap::new();
+        let mut lenses = HashMap::new();
+        parse_lcov(
+            &lcov,
+            "file:///tmp/dummy.c",
+            temp_dir.path().to_str().unwrap(),
+            &mut diags,
+            &mut lenses,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let d = diags.get(&uri).expect("coverage hints missing");
+        assert_eq!(d.len(), 2);
+        assert_eq!(d[0]["severity"], json!(4));
+        assert_eq!(d[0]["range"]["start"]["line"], json!(3));
+        assert_eq!(d[1]["range"]["start"]["line"], json!(9));
+        assert_eq!(d[1]["range"]["end"]["line"], json!(12));
+        assert_eq!(d[1]["message"], json!("never executed (3 lines)"));
+
+        let l = lenses.get(&uri).expect("code lens missing");
+        assert_eq!(l.len(), 2);
+        assert_eq!(l[0]["command"]["title"], json!("main called 2 times"));
+        assert_eq!(l[1]["range"]["start"]["line"], json!(9));
+    }
+}
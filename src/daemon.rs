@@ -0,0 +1,229 @@
+use crate::SharedClients;
+use crate::SharedStore;
+use crate::runtime;
+use crate::transport;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc::UnboundedSender;
+
+// how long `attach` waits for a daemon it started
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+// the socket editors of the workspace attach to
+pub fn socket_path(name: &str) -> PathBuf {
+    PathBuf::from(format!("{}.lsp.sock", name))
+}
+
+// serves each editor which connects with its own LSP session. the logs are watched and
+// parsed once for all of them, returns when the last one leaves
+pub async fn serve(
+    path: &Path,
+    store: SharedStore,
+    clients: SharedClients,
+    config_tx: UnboundedSender<Vec<PathBuf>>,
+) -> Result<()> {
+    let mut listener = runtime::bind_socket(path)?;
+    // the socket at `path` is still ours
+    let mut bound = true;
+    let (left_tx, mut left_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+    let mut sessions = 0usize;
+    let spawn_session = |conn: UnixStream| {
+        let (reader, writer) = conn.into_split();
+        let (store, clients, config_tx) = (store.clone(), clients.clone(), config_tx.clone());
+        let left_tx = left_tx.clone();
+        tokio::spawn(async move {
+            transport::session(
+                reader,
+                writer,
+                Some(HashSet::new()),
+                store,
+                clients,
+                config_tx,
+            )
+            .await;
+            let _ = left_tx.send(());
+        });
+    };
+    loop {
+        tokio::select! {
+            res = listener.accept() => {
+                let (conn, _) = res?;
+                sessions += 1;
+                spawn_session(conn);
+            }
+            Some(()) = left_rx.recv() => {
+                // nothing is accepted until it is decided whether to exit
+                sessions -= 1;
+                if sessions > 0 || !clients.lock().await.is_empty() {
+                    continue;
+                }
+                if bound {
+                    let _ = std::fs::remove_file(path);
+                }
+                // editors which connected before the socket was removed
+                let (l, late) = accept_pending(listener)?;
+                listener = l;
+                if late.is_empty() {
+                    break;
+                }
+                for conn in late {
+                    sessions += 1;
+                    spawn_session(conn);
+                }
+                // unless a new daemon took the path meanwhile
+                match runtime::bind_socket(path) {
+                    Ok(l) => listener = l,
+                    Err(_) => bound = false,
+                }
+            }
+        }
+    }
+    store.lock().await.save_cache();
+    Ok(())
+}
+
+// the connections waiting to be accepted, without waiting for more. the socket is asked
+// itself, tokio only knows of a connection once its reactor has seen the event
+fn accept_pending(listener: UnixListener) -> Result<(UnixListener, Vec<UnixStream>)> {
+    let listener = listener.into_std()?;
+    listener.set_nonblocking(true)?;
+    let mut conns = Vec::new();
+    while let Ok((conn, _)) = listener.accept() {
+        if conn.set_nonblocking(true).is_ok()
+            && let Ok(conn) = UnixStream::from_std(conn)
+        {
+            conns.push(conn);
+        }
+    }
+    Ok((UnixListener::from_std(listener)?, conns))
+}
+
+// the same command line with `attach` replaced by `daemon`, detached from the editor.
+// its stderr goes to <name>.daemon.log
+fn spawn_daemon(name: &str) -> Result<()> {
+    let mut replaced = false;
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .map(|a| {
+            if !replaced && a == "attach" {
+                replaced = true;
+                "daemon".to_string()
+            } else {
+                a
+            }
+        })
+        .collect();
+    let log = runtime::open_log(&format!("{}.daemon.log", name), true)?;
+    Command::new(std::env::current_exe()?)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(log)
+        .process_group(0)
+        .spawn()
+        .context("failed to start the daemon")?;
+    Ok(())
+}
+
+// connects stdio to the daemon of the workspace, starting it when there is none
+pub async fn attach(name: &str) -> Result<()> {
+    let path = socket_path(name);
+    let conn = match UnixStream::connect(&path).await {
+        Ok(conn) => conn,
+        Err(_) => {
+            spawn_daemon(name)?;
+            let start = tokio::time::Instant::now();
+            loop {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                match UnixStream::connect(&path).await {
+                    Ok(conn) => break conn,
+                    Err(e) if start.elapsed() > STARTUP_TIMEOUT => {
+                        return Err(e)
+                            .with_context(|| format!("failed to connect {}", path.display()));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    };
+    let (mut reader, mut writer) = conn.into_split();
+    let up = tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut tokio::io::stdin(), &mut writer).await;
+        let _ = writer.shutdown().await;
+    });
+    // the daemon closes the connection after exit, or when the editor closed stdin
+    tokio::io::copy(&mut reader, &mut tokio::io::stdout()).await?;
+    up.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsp_io::{read_lsp_message, write_lsp_message};
+    use serde_json::json;
+    use tokio::io::BufReader;
+
+    async fn attach_client(path: &Path, id: u64) -> UnixStream {
+        let mut conn = UnixStream::connect(path).await.unwrap();
+        let msg = json!({"jsonrpc": "2.0", "id": id, "method": "initialize", "params": {}});
+        write_lsp_message(&mut conn, &msg).await.unwrap();
+        let (reader, _) = conn.split();
+        let reply = read_lsp_message(&mut BufReader::new(reader)).await.unwrap();
+        assert_eq!(reply["id"], json!(id));
+        conn
+    }
+
+    async fn detach(mut conn: UnixStream) {
+        let msg = json!({"jsonrpc": "2.0", "method": "exit"});
+        write_lsp_message(&mut conn, &msg).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn serves_until_the_last_editor_leaves() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let run_dir = runtime::runtime_dir(Some(temp_dir.path().into()));
+        let name = runtime::workspace_name(&run_dir, temp_dir.path()).unwrap();
+        let path = socket_path(&name);
+        let (config_tx, _config_rx) = tokio::sync::mpsc::unbounded_channel();
+        let server = tokio::spawn({
+            let path = path.clone();
+            async move {
+                serve(
+                    &path,
+                    SharedStore::default(),
+                    SharedClients::default(),
+                    config_tx,
+                )
+                .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let first = attach_client(&path, 1).await;
+        let second = attach_client(&path, 2).await;
+        detach(first).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server.is_finished());
+
+        // an editor attaching while the last one leaves is still served
+        detach(second).await;
+        let third = attach_client(&path, 3).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!server.is_finished());
+
+        detach(third).await;
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("the daemon kept running")
+            .unwrap()
+            .unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,153 @@
+use serde_json::{Value, json};
+
+// (line, character), characters in UTF-16 code units like in LSP
+type Position = (u64, u64);
+
+fn position(v: &Value) -> Option<Position> {
+    Some((v["line"].as_u64()?, v["character"].as_u64().unwrap_or(0)))
+}
+
+// one contentChanges entry of textDocument/didChange
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEdit {
+    // the replaced range
+    pub start: Position,
+    pub end: Position,
+    // newlines in the inserted text, and the length of its last line
+    pub inserted: u64,
+    pub last_len: u64,
+}
+
+impl LineEdit {
+    // None for a change without a range, which replaces the whole document
+    pub fn from_change(change: &Value) -> Option<Self> {
+        let range = change.get("range")?;
+        let start = position(&range["start"])?;
+        let end = position(&range["end"])?.max(start);
+        let text = change["text"].as_str().unwrap_or_default();
+        let last = text.rsplit('\n').next().unwrap_or_default();
+        Some(LineEdit {
+            start,
+            end,
+            inserted: text.matches('\n').count() as u64,
+            last_len: last.encode_utf16().count() as u64,
+        })
+    }
+
+    // a position in the replaced text ends up after the inserted one
+    fn shift(&self, pos: Position) -> Position {
+        if pos < self.start {
+            return pos;
+        }
+        let (line, character) = pos.max(self.end);
+        let new_line = line + self.inserted - (self.end.0 - self.start.0);
+        if line != self.end.0 {
+            return (new_line, character);
+        }
+        let first = if self.inserted == 0 { self.start.1 } else { 0 };
+        (new_line, first + self.last_len + (character - self.end.1))
+    }
+
+    // moves a range like the text it points at, returns whether the edit touched the code
+    // from its start to the end of its last line. text put in front of it, like a newline
+    // at the start of its line, only moves it
+    pub fn apply(&self, range: &mut Value) -> bool {
+        let (Some(start), Some(end)) = (position(&range["start"]), position(&range["end"])) else {
+            return false;
+        };
+        for (key, pos) in [("start", start), ("end", end)] {
+            let (line, character) = self.shift(pos);
+            range[key] = json!({ "line": line, "character": character });
+        }
+        self.end > start && self.start.0 <= end.0
+    }
+}
+
+// the code under a stale diagnostic was edited after the log was written,
+// it is shown as information until a new build or run replaces it
+pub fn mark_stale(diag: &mut Value) {
+    if diag["data"]["stale"].as_bool() == Some(true) {
+        return;
+    }
+    if let Some(msg) = diag["message"].as_str() {
+        diag["message"] = json!(format!("[stale] {}", msg));
+    }
+    if diag["severity"].as_u64().is_some_and(|s| s < 3) {
+        diag["severity"] = json!(3);
+    }
+    diag["data"]["stale"] = json!(true);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diag(line: u64) -> Value {
+        json!({
+            "range": {
+                "start": { "line": line, "character": 4 },
+                "end": { "line": line, "character": 5 }
+            },
+            "severity": 1,
+            "message": "boom"
+        })
+    }
+
+    #[test]
+    fn shifts_and_marks_stale() {
+        // two lines inserted after line 2
+        let insert = LineEdit::from_change(&json!({
+            "range": {
+                "start": { "line": 2, "character": 10 },
+                "end": { "line": 2, "character": 10 }
+            },
+            "text": "\n  a();\n  b();"
+        }))
+        .unwrap();
+        let mut below = diag(5);
+        assert!(!insert.apply(&mut below["range"]));
+        assert_eq!(below["range"]["start"]["line"], json!(7));
+        let mut above = diag(1);
+        assert!(!insert.apply(&mut above["range"]));
+        assert_eq!(above["range"]["end"]["line"], json!(1));
+
+        // Enter at the start of the line only moves it
+        let enter = LineEdit::from_change(&json!({
+            "range": {
+                "start": { "line": 5, "character": 0 },
+                "end": { "line": 5, "character": 0 }
+            },
+            "text": "\n"
+        }))
+        .unwrap();
+        let mut moved = diag(5);
+        assert!(!enter.apply(&mut moved["range"]));
+        assert_eq!(
+            moved["range"]["start"],
+            json!({ "line": 6, "character": 4 })
+        );
+        assert_eq!(moved["range"]["end"], json!({ "line": 6, "character": 5 }));
+
+        // lines 3..=4 joined into one, along with the code it points at
+        let join = LineEdit::from_change(&json!({
+            "range": {
+                "start": { "line": 3, "character": 0 },
+                "end": { "line": 4, "character": 6 }
+            },
+            "text": "x"
+        }))
+        .unwrap();
+        let mut edited = diag(4);
+        assert!(join.apply(&mut edited["range"]));
+        assert_eq!(
+            edited["range"]["start"],
+            json!({ "line": 3, "character": 1 })
+        );
+        mark_stale(&mut edited);
+        mark_stale(&mut edited);
+        assert_eq!(edited["message"], json!("[stale] boom"));
+        assert_eq!(edited["severity"], json!(3));
+
+        assert!(LineEdit::from_change(&json!({ "text": "whole file" })).is_none());
+    }
+}
@@ -0,0 +1,400 @@
+use crate::config::UserParser;
+use crate::log_parser::make_uri;
+use anyhow::{Result, bail};
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::Path;
+
+// vim 'errorformat' interpreter, see :help errorformat
+// ex. %f:%l:%c:%t%*[^:]: %m,%-G%.%#
+//     %E%f:%l: error: %m,%C  %m,%Z
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Single,
+    Start(Option<char>), // %A %E %W %I %N
+    Continue,            // %C
+    End,                 // %Z
+    General,             // %G
+    EnterDir,            // %D
+    LeaveDir,            // %X
+    PushFile,            // %P
+    PopFile,             // %Q
+    FileOnly,            // %O
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Flag {
+    None,
+    Minus, // %- : the line is not part of the message
+    Plus,  // %+ : the whole line is the message
+}
+
+#[derive(Debug, Clone)]
+struct Item {
+    kind: Kind,
+    flag: Flag,
+    regex: Regex,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorFormat {
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    file: Option<String>,
+    line: Option<u64>,
+    col: Option<u64>,
+    end_line: Option<u64>,
+    end_col: Option<u64>,
+    kind: Option<char>,
+    code: Option<String>,
+    message: Vec<String>,
+}
+
+impl ErrorFormat {
+    pub fn parse(formats: &[String]) -> Result<Self> {
+        let mut items = Vec::new();
+        for f in formats.iter().flat_map(|f| split_errorformat(f)) {
+            if f.is_empty() {
+                continue;
+            }
+            items.push(compile_item(&f)?);
+        }
+        Ok(ErrorFormat { items })
+    }
+
+    // the line starts a multi-line entry, %A %E %W %I %N
+    pub fn starts_entry(&self, line: &str) -> bool {
+        self.items
+            .iter()
+            .find(|item| item.regex.is_match(line))
+            .is_some_and(|item| matches!(item.kind, Kind::Start(_)))
+    }
+}
+
+// "a,b\,c" -> ["a", "b,c"], as written in :set errorformat=
+fn split_errorformat(efm: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut cur = String::new();
+    let mut chars = efm.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(',' | '\\' | ' ')) => {
+                cur.push(chars.next().unwrap_or_default());
+            }
+            ',' => out.push(std::mem::take(&mut cur)),
+            _ => cur.push(c),
+        }
+    }
+    out.push(cur);
+    out
+}
+
+fn compile_item(fmt: &str) -> Result<Item> {
+    let (flag, body) = if let Some(r) = fmt.strip_prefix("%-") {
+        (Flag::Minus, r)
+    } else if let Some(r) = fmt.strip_prefix("%+") {
+        (Flag::Plus, r)
+    } else {
+        (Flag::None, fmt.strip_prefix('%').unwrap_or(""))
+    };
+    let kind = match body.chars().next() {
+        Some('A') => Some(Kind::Start(None)),
+        Some(c @ ('E' | 'W' | 'I' | 'N')) => Some(Kind::Start(Some(c.to_ascii_lowercase()))),
+        Some('C') => Some(Kind::Continue),
+        Some('Z') => Some(Kind::End),
+        Some('G') => Some(Kind::General),
+        Some('D') => Some(Kind::EnterDir),
+        Some('X') => Some(Kind::LeaveDir),
+        Some('P') => Some(Kind::PushFile),
+        Some('Q') => Some(Kind::PopFile),
+        Some('O') => Some(Kind::FileOnly),
+        _ => None,
+    };
+    let (kind, rest) = match kind {
+        Some(k) => (k, &body[1..]),
+        None if flag != Flag::None => bail!("errorformat {}: %- and %+ need a prefix letter", fmt),
+        None => (Kind::Single, fmt),
+    };
+
+    let mut re = String::from("^");
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            re.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+        let Some(conv) = chars.next() else {
+            bail!("errorformat {}: trailing %", fmt);
+        };
+        let last = chars.peek().is_none();
+        let group = |name: &str, pat: &str| format!("(?P<{name}>{pat})");
+        match conv {
+            'f' => re.push_str(&group("f", if last { ".+" } else { ".+?" })),
+            'o' => re.push_str(&group("o", ".+?")),
+            'l' => re.push_str(&group("l", r"\d+")),
+            'c' => re.push_str(&group("c", r"\d+")),
+            'v' => re.push_str(&group("v", r"\d+")),
+            'e' => re.push_str(&group("e", r"\d+")),
+            'k' => re.push_str(&group("k", r"\d+")),
+            'n' => re.push_str(&group("n", r"\d+")),
+            't' => re.push_str(&group("t", ".")),
+            'm' => re.push_str(&group("m", if last { ".*" } else { ".*?" })),
+            'r' => re.push_str(&group("r", ".*")),
+            's' => re.push_str(&group("s", ".+")),
+            'p' => re.push_str(&group("p", r"[-\t .]*")),
+            '*' => {
+                // %*[^:] or %*\d ... skip one or more
+                let mut class = String::new();
+                match chars.next() {
+                    Some('[') => {
+                        class.push('[');
+                        for ch in chars.by_ref() {
+                            class.push(ch);
+                            if ch == ']' && class.len() > 2 {
+                                break;
+                            }
+                        }
+                    }
+                    Some('\\') => {
+                        class.push('\\');
+                        class.extend(chars.next());
+                    }
+                    Some(ch) => class.push_str(&regex::escape(&ch.to_string())),
+                    None => bail!("errorformat {}: trailing %*", fmt),
+                }
+                re.push_str(&format!("(?:{class})+"));
+            }
+            '%' => re.push('%'),
+            '.' => re.push('.'),
+            '#' => re.push('*'),
+            '^' | '$' | '[' | ']' | '~' | '\\' => re.push(conv),
+            '>' => {}
+            other => bail!("errorformat {}: unsupported %{}", fmt, other),
+        }
+    }
+    re.push('$');
+
+    let regex = Regex::new(&re).map_err(|e| anyhow::anyhow!("errorformat {}: {}", fmt, e))?;
+    Ok(Item { kind, flag, regex })
+}
+
+pub fn parse_errorformat(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    efm: &ErrorFormat,
+    parser: &UserParser,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    let mut dirs: Vec<String> = Vec::new();
+    let mut files: Vec<String> = Vec::new();
+    let mut current: Option<Entry> = None;
+
+    let mut flush = |entry: Entry, dirs: &[String], files: &[String]| {
+        let Some(line) = entry.line else {
+            return;
+        };
+        let file = entry.file.or_else(|| files.last().cloned());
+        let uri = match file {
+            Some(f) => {
+                let f = match dirs.last() {
+                    Some(d) if !Path::new(&f).is_absolute() => format!("{}/{}", d, f),
+                    _ => f,
+                };
+                make_uri(&f, saved_uri, root_path)
+            }
+            None => saved_uri.to_string(),
+        };
+        let line = line.saturating_sub(1);
+        let col = entry.col.unwrap_or(1).saturating_sub(1);
+        let end_line = entry.end_line.map(|l| l.saturating_sub(1)).unwrap_or(line);
+        let end_col = entry.end_col.unwrap_or(col + 1);
+        let end_col = if end_line == line {
+            end_col.max(col + 1)
+        } else {
+            end_col
+        };
+        let sev = match entry.kind {
+            Some(t) => parser.severity.get(&t.to_string()).copied().unwrap_or(
+                match t.to_ascii_lowercase() {
+                    'w' => 2,
+                    'i' | 'n' => 3,
+                    'h' => 4,
+                    _ => 1,
+                },
+            ),
+            None => parser.default_severity,
+        };
+        let message = entry.message.join("\n").trim().to_string();
+        let message = if message.is_empty() {
+            parser.name.clone()
+        } else {
+            message
+        };
+
+        let mut diag = json!({
+            "range": {
+                "start": { "line": line, "character": col },
+                "end":   { "line": end_line, "character": end_col }
+            },
+            "severity": sev,
+            "source": parser.source,
+            "message": message
+        });
+        if let Some(code) = entry.code {
+            diag["code"] = json!(code);
+        }
+        out.entry(uri).or_default().push(diag);
+    };
+
+    for line in text.lines() {
+        let matched = efm.items.iter().find_map(|item| {
+            // %C and %Z only continue an entry which has already started
+            if matches!(item.kind, Kind::Continue | Kind::End) && current.is_none() {
+                return None;
+            }
+            item.regex.captures(line).map(|cap| (item, cap))
+        });
+        let Some((item, cap)) = matched else {
+            continue;
+        };
+        let get = |name: &str| cap.name(name).map(|m| m.as_str().to_string());
+        let num = |name: &str| cap.name(name).and_then(|m| m.as_str().parse::<u64>().ok());
+
+        let mut fields = Entry {
+            file: get("f"),
+            line: num("l"),
+            col: num("c")
+                .or_else(|| num("v"))
+                .or_else(|| cap.name("p").map(|p| p.as_str().len() as u64 + 1)),
+            end_line: num("e"),
+            end_col: num("k"),
+            kind: get("t").and_then(|t| t.chars().next()),
+            code: get("n"),
+            message: Vec::new(),
+        };
+        match item.flag {
+            Flag::Plus => fields.message.push(line.to_string()),
+            Flag::None => fields.message.extend(get("m")),
+            Flag::Minus => {}
+        }
+
+        match item.kind {
+            Kind::Single => {
+                if item.flag == Flag::Minus {
+                    continue;
+                }
+                if let Some(e) = current.take() {
+                    flush(e, &dirs, &files);
+                }
+                flush(fields, &dirs, &files);
+            }
+            Kind::Start(t) => {
+                if let Some(e) = current.take() {
+                    flush(e, &dirs, &files);
+                }
+                fields.kind = fields.kind.or(t);
+                current = Some(fields);
+            }
+            Kind::Continue | Kind::End => {
+                if let Some(e) = current.as_mut() {
+                    e.file = e.file.take().or(fields.file);
+                    e.line = e.line.or(fields.line);
+                    e.col = e.col.or(fields.col);
+                    e.end_line = e.end_line.or(fields.end_line);
+                    e.end_col = e.end_col.or(fields.end_col);
+                    e.kind = e.kind.or(fields.kind);
+                    e.code = e.code.take().or(fields.code);
+                    e.message.extend(fields.message);
+                }
+                if item.kind == Kind::End
+                    && let Some(e) = current.take()
+                {
+                    flush(e, &dirs, &files);
+                }
+            }
+            Kind::General => {}
+            Kind::EnterDir => dirs.extend(get("f")),
+            Kind::LeaveDir => {
+                dirs.pop();
+            }
+            Kind::PushFile => files.extend(get("f")),
+            Kind::PopFile => {
+                files.pop();
+            }
+            Kind::FileOnly => {}
+        }
+    }
+    if let Some(e) = current.take() {
+        flush(e, &dirs, &files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ParserConfig, UserParser};
+
+    #[test]
+    fn interprets_vim_errorformat() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("main.c");
+        std::fs::write(&file_path, "int main(void) {}").unwrap();
+
+        let cfg: ParserConfig = toml::from_str(
+            r#"
+            name = "efm"
+            errorformat = [
+                '%f:%l:%c: %t%*[^:]: %m',
+                '%E%f:%l: error: %m,%C  %m,%Z',
+                '%-G%.%#',
+            ]
+            "#,
+        )
+        .unwrap();
+        let parser = UserParser::compile(&cfg).unwrap();
+        let crate::config::Matcher::ErrorFormat(efm) = &parser.matcher else {
+            panic!("errorformat parser expected");
+        };
+
+        let log = format!(
+            "make: Entering directory\n\
+             {f}:3:7: warning: unused variable 'x'\n\
+             {f}:9: error: conflicting types\n\
+             \x20 for 'foo'\n\
+             \n",
+            f = file_path.display()
+        );
+        let mut out = HashMap::new();
+        parse_errorformat(
+            &log,
+            "file:///tmp/dummy.c",
+            temp_dir.path().to_str().unwrap(),
+            efm,
+            &parser,
+            &mut out,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = out.get(&uri).expect("diagnostic missing for errorformat");
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["severity"], json!(2));
+        assert_eq!(diags[0]["range"]["start"]["character"], json!(6));
+        assert_eq!(diags[0]["message"], json!("unused variable 'x'"));
+        assert_eq!(diags[1]["severity"], json!(1));
+        assert_eq!(diags[1]["range"]["start"]["line"], json!(8));
+        assert_eq!(diags[1]["message"], json!("conflicting types\nfor 'foo'"));
+
+        // the %E line may be continued in the next chunk of the log
+        let start = log.find(&format!("{}:9:", file_path.display())).unwrap();
+        assert_eq!(
+            crate::log_parser::last_record_start(&log, std::slice::from_ref(&parser)),
+            start
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::ingest::MARKER;
+use crate::runtime;
+use anyhow::{Context, Result, bail};
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+// sanitizer settings which make the logs parseable, added unless the user set them
+const SANITIZER_DEFAULTS: &[(&str, &str, &str)] = &[
+    ("ASAN_OPTIONS", "symbolize", "1"),
+    ("UBSAN_OPTIONS", "print_stacktrace", "1"),
+];
+
+// appends key=value to a sanitizer option string like "detect_leaks=0:halt_on_error=1"
+fn with_default(options: Option<&str>, key: &str, value: &str) -> String {
+    match options.filter(|o| !o.is_empty()) {
+        Some(o)
+            if o.split([':', ' '])
+                .any(|kv| kv.split('=').next() == Some(key)) =>
+        {
+            o.to_string()
+        }
+        Some(o) => format!("{}:{}={}", o, key, value),
+        None => format!("{}={}", key, value),
+    }
+}
+
+// copies one output of the child to the terminal and, line by line, to the log
+async fn tee<R, W>(from: R, mut to: W, log: Arc<Mutex<File>>) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut reader = BufReader::new(from);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).await? == 0 {
+            return Ok(());
+        }
+        to.write_all(&buf).await?;
+        to.flush().await?;
+        if let Ok(mut log) = log.lock() {
+            log.write_all(&buf)?;
+        }
+    }
+}
+
+// runs `cmd`, tees its output into `log` between run markers, returns the exit code to use
+pub async fn exec(log_path: &str, label: Option<&str>, cmd: &[String]) -> Result<i32> {
+    let Some((program, args)) = cmd.split_first() else {
+        bail!("no command given");
+    };
+    let label = label.map(str::to_string).unwrap_or_else(|| {
+        std::path::Path::new(program)
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_else(|| program.clone())
+    });
+    let mut log = runtime::open_log(log_path, false)
+        .with_context(|| format!("failed to open {}", log_path))?;
+    writeln!(log, "{}run-start {}", MARKER, label)?;
+    let log = Arc::new(Mutex::new(log));
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    for (var, key, value) in SANITIZER_DEFAULTS {
+        let current = std::env::var(var).ok();
+        command.env(var, with_default(current.as_deref(), key, value));
+    }
+    let started = Instant::now();
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("failed to run {}", program))?;
+    let out = tokio::spawn(tee(
+        child.stdout.take().expect("piped stdout"),
+        tokio::io::stdout(),
+        log.clone(),
+    ));
+    let err = tokio::spawn(tee(
+        child.stderr.take().expect("piped stderr"),
+        tokio::io::stderr(),
+        log.clone(),
+    ));
+    let status = child.wait().await?;
+    for copy in [out, err] {
+        if let Ok(Err(e)) = copy.await {
+            eprintln!("[clasangd] {:#}", e);
+        }
+    }
+
+    let (result, code) = match (status.code(), status.signal()) {
+        (Some(code), _) => (format!("exit={}", code), code),
+        (None, Some(sig)) => (format!("signal={}", sig), 128 + sig),
+        (None, None) => ("exit=?".to_string(), 1),
+    };
+    if let Ok(mut log) = log.lock() {
+        writeln!(
+            log,
+            "{}run-end {} duration={:.3}s",
+            MARKER,
+            result,
+            started.elapsed().as_secs_f64()
+        )?;
+    }
+    Ok(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_sanitizer_defaults() {
+        assert_eq!(with_default(None, "symbolize", "1"), "symbolize=1");
+        assert_eq!(
+            with_default(Some("detect_leaks=0"), "symbolize", "1"),
+            "detect_leaks=0:symbolize=1"
+        );
+        assert_eq!(
+            with_default(Some("symbolize=0:detect_leaks=0"), "symbolize", "1"),
+            "symbolize=0:detect_leaks=0"
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_run_markers() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let log = temp_dir.path().join("run.log");
+        let log = log.to_str().unwrap();
+        let cmd = ["sh", "-c", "echo out; echo err >&2; exit 3"].map(String::from);
+        assert_eq!(exec(log, Some("t"), &cmd).await.unwrap(), 3);
+
+        let text = std::fs::read_to_string(log).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "@clasangd run-start t");
+        assert!(lines.contains(&"out") && lines.contains(&"err"));
+        assert!(lines[3].starts_with("@clasangd run-end exit=3 duration="));
+    }
+}
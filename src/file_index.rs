@@ -0,0 +1,263 @@
+use crate::remap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+// the files of each workspace root by name, built on first use and kept up to date
+// from the events of the root watcher
+static INDEXES: LazyLock<Mutex<HashMap<PathBuf, FileIndex>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// (path in the log, root) -> file
+type Resolved = HashMap<(String, String), Option<PathBuf>>;
+
+thread_local! {
+    // while a log is parsed
+    static RESOLVED: RefCell<Option<Resolved>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct FileIndex {
+    by_name: HashMap<String, Vec<PathBuf>>,
+    // the directories walked, each one is watched on its own
+    dirs: HashSet<PathBuf>,
+}
+
+pub fn is_ignore_dir(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| {
+            n.starts_with('.')
+                || n == "target"
+                || n == "node_modules"
+                || n == "build"
+                || n == "dist"
+        })
+        .unwrap_or(false)
+}
+
+// not followed, it may point back up the tree
+fn is_dir_link(path: &Path) -> bool {
+    path.symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_symlink())
+        && path.is_dir()
+}
+
+impl FileIndex {
+    fn insert(&mut self, path: PathBuf) {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            return;
+        };
+        let paths = self.by_name.entry(name.to_string()).or_default();
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    // a file, or every file below a directory
+    fn add_tree(&mut self, path: &Path) {
+        let mut stack = vec![path.to_path_buf()];
+        while let Some(p) = stack.pop() {
+            if p.is_file() {
+                self.insert(p);
+            } else if let Ok(entries) = std::fs::read_dir(&p) {
+                self.dirs.insert(p);
+                stack.extend(
+                    entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .filter(|c| !(c.is_dir() && (is_ignore_dir(c) || is_dir_link(c)))),
+                );
+            }
+        }
+    }
+
+    fn remove_tree(&mut self, path: &Path) {
+        for paths in self.by_name.values_mut() {
+            paths.retain(|p| !p.starts_with(path));
+        }
+        self.by_name.retain(|_, paths| !paths.is_empty());
+        self.dirs.retain(|d| !d.starts_with(path));
+    }
+
+    // the file whose path ends with most of `wanted`, then the shallowest, then by name
+    fn find(&self, wanted: &Path) -> Option<PathBuf> {
+        let name = wanted.file_name()?.to_str()?;
+        let wanted: Vec<&std::ffi::OsStr> = wanted
+            .components()
+            .rev()
+            .map_while(|c| match c {
+                Component::Normal(n) => Some(n),
+                _ => None,
+            })
+            .collect();
+        self.by_name
+            .get(name)?
+            .iter()
+            .max_by(|a, b| {
+                let score = |p: &Path| {
+                    let common = p
+                        .components()
+                        .rev()
+                        .zip(&wanted)
+                        .take_while(|(c, w)| c.as_os_str() == **w)
+                        .count();
+                    (common, std::cmp::Reverse(p.components().count()))
+                };
+                score(a).cmp(&score(b)).then_with(|| b.cmp(a))
+            })
+            .cloned()
+    }
+}
+
+fn with_index<T>(root: &Path, f: impl FnOnce(&FileIndex) -> T) -> Option<T> {
+    if !root.is_dir() {
+        return None;
+    }
+    let mut indexes = INDEXES.lock().unwrap_or_else(|e| e.into_inner());
+    let index = indexes.entry(root.to_path_buf()).or_insert_with(|| {
+        let mut index = FileIndex::default();
+        index.add_tree(root);
+        index
+    });
+    Some(f(index))
+}
+
+// `p` below the workspace root, looked up in its index
+fn find_in_root(p: &str, root: &str) -> Option<PathBuf> {
+    with_index(Path::new(root), |index| index.find(Path::new(p)))?
+}
+
+// the directories of the root which are indexed, without target/, .git/ and the like
+pub fn dirs(root: &Path) -> Vec<PathBuf> {
+    with_index(root, |index| index.dirs.iter().cloned().collect()).unwrap_or_default()
+}
+
+// the file a path in a log names, if it can be found. a remapped path is tried first, then
+// the path as written, ex. "lib/a.c" with a rule for "lib" may be the workspace's own file
+pub fn resolve_path(p: &str, root: &str) -> Option<PathBuf> {
+    let key = (p.to_string(), root.to_string());
+    if let Some(hit) = RESOLVED.with(|r| r.borrow().as_ref().and_then(|m| m.get(&key).cloned())) {
+        return hit;
+    }
+    let exact = |p: &str| {
+        std::fs::canonicalize(p)
+            .or_else(|_| std::fs::canonicalize(format!("{}/{}", root, p)))
+            .ok()
+    };
+    let remapped = remap::apply(p, root);
+    let found = remapped
+        .as_deref()
+        .and_then(exact)
+        .or_else(|| exact(p))
+        .or_else(|| find_in_root(remapped.as_deref().unwrap_or(p), root));
+    RESOLVED.with(|r| {
+        if let Some(m) = r.borrow_mut().as_mut() {
+            m.insert(key, found.clone());
+        }
+    });
+    found
+}
+
+// resolved paths are remembered until the returned guard is dropped
+pub struct ParseScope {
+    owner: bool,
+}
+
+pub fn parse_scope() -> ParseScope {
+    RESOLVED.with(|r| {
+        let mut r = r.borrow_mut();
+        let owner = r.is_none();
+        if owner {
+            *r = Some(HashMap::new());
+        }
+        ParseScope { owner }
+    })
+}
+
+impl Drop for ParseScope {
+    fn drop(&mut self) {
+        if self.owner {
+            RESOLVED.with(|r| *r.borrow_mut() = None);
+        }
+    }
+}
+
+// a path under a root was created, removed or renamed
+pub fn update(path: &Path) {
+    let mut indexes = INDEXES.lock().unwrap_or_else(|e| e.into_inner());
+    for (root, index) in indexes.iter_mut() {
+        let Ok(rel) = path.strip_prefix(root) else {
+            continue;
+        };
+        // like add_tree, a dot file is indexed but nothing in an ignored directory
+        if rel.ancestors().skip(1).any(is_ignore_dir)
+            || (path.is_dir() && is_ignore_dir(path))
+            || is_dir_link(path)
+        {
+            continue;
+        }
+        if path.exists() {
+            index.add_tree(path);
+        } else {
+            index.remove_tree(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_by_longest_suffix() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path();
+        for f in [
+            "src/util.c",
+            "tests/util.c",
+            "src/net/util.c",
+            "target/util.c",
+        ] {
+            std::fs::create_dir_all(root.join(f).parent().unwrap()).unwrap();
+            std::fs::write(root.join(f), "").unwrap();
+        }
+        let root_str = root.to_str().unwrap();
+        assert_eq!(
+            find_in_root("/home/ci/proj/tests/util.c", root_str),
+            Some(root.join("tests/util.c"))
+        );
+        assert_eq!(
+            find_in_root("../net/util.c", root_str),
+            Some(root.join("src/net/util.c"))
+        );
+        // a bare name prefers the shallowest file
+        assert_eq!(
+            find_in_root("util.c", root_str),
+            Some(root.join("src/util.c"))
+        );
+
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("lib/new.c"), "").unwrap();
+        update(&root.join("lib"));
+        assert_eq!(
+            find_in_root("new.c", root_str),
+            Some(root.join("lib/new.c"))
+        );
+        assert!(dirs(root).contains(&root.join("lib")));
+        assert!(!dirs(root).contains(&root.join("target")));
+        // a link back up the tree is not walked
+        std::os::unix::fs::symlink(root, root.join("src/loop")).unwrap();
+        update(&root.join("src/loop"));
+        assert!(!dirs(root).contains(&root.join("src/loop")));
+        assert_eq!(
+            find_in_root("loop/util.c", root_str),
+            Some(root.join("src/util.c"))
+        );
+        std::fs::remove_dir_all(root.join("lib")).unwrap();
+        update(&root.join("lib"));
+        assert_eq!(find_in_root("new.c", root_str), None);
+        assert!(!dirs(root).contains(&root.join("lib")));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::edit;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+// shorter source text, like "}" or "return;", matches too many lines to relocate by
+const MIN_FINGERPRINT: usize = 6;
+
+// how far a quoted line is looked for, farther away the same text is likely other code
+const WINDOW: usize = 50;
+
+// the normalized lines of every source relocated in, read again once the file was written
+type Sources = HashMap<String, (Option<SystemTime>, u64, Arc<Vec<String>>)>;
+static SOURCES: LazyLock<Mutex<Sources>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn normalize(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn source_lines(path: &str) -> Option<Arc<Vec<String>>> {
+    let meta = std::fs::metadata(path).ok()?;
+    let stamp = (meta.modified().ok(), meta.len());
+    let mut sources = SOURCES.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((modified, len, lines)) = sources.get(path)
+        && (*modified, *len) == stamp
+    {
+        return Some(lines.clone());
+    }
+    let text = std::fs::read_to_string(path).ok()?;
+    let lines = Arc::new(text.lines().map(normalize).collect::<Vec<_>>());
+    sources.insert(path.to_string(), (stamp.0, stamp.1, lines.clone()));
+    Some(lines)
+}
+
+// the source line a compiler quotes under its message, if the line after it is the caret line
+// ex. clang
+//     int x = a + b;
+//             ^
+// ex. gcc
+//    12 |     int x = a + b;
+//       |             ^
+pub fn compiler_snippet(code: &str, caret: Option<&str>) -> Option<String> {
+    let gutter = Regex::new(r"^\s*\d*\s*\| ?").expect("invalid regex");
+    let caret = gutter.replace(caret?, "");
+    if !caret.contains('^') || !caret.chars().all(|c| matches!(c, ' ' | '^' | '~' | '\t')) {
+        return None;
+    }
+    let code = gutter.replace(code, "");
+    Some(code.trim_end().to_string()).filter(|c| !c.trim().is_empty())
+}
+
+// moves diagnostics whose quoted source line is no longer at their line to the nearest line
+// within WINDOW lines which still has it. the ones whose line can't be found are marked stale
+pub fn relocate(diags: &mut HashMap<String, Vec<Value>>) {
+    for (uri, list) in diags.iter_mut() {
+        if !list.iter().any(|d| d["data"]["fingerprint"].is_string()) {
+            continue;
+        }
+        let Some(lines) = uri.strip_prefix("file://").and_then(source_lines) else {
+            continue;
+        };
+        for d in list.iter_mut() {
+            let Some(fp) = d["data"]["fingerprint"].as_str().map(normalize) else {
+                continue;
+            };
+            let Some(line) = d["range"]["start"]["line"].as_u64().map(|l| l as usize) else {
+                continue;
+            };
+            if fp.len() < MIN_FINGERPRINT || lines.get(line) == Some(&fp) {
+                continue;
+            }
+            let found = (1..=WINDOW).find_map(|dist| {
+                [line.checked_sub(dist), Some(line + dist)]
+                    .into_iter()
+                    .flatten()
+                    .find(|l| lines.get(*l) == Some(&fp))
+            });
+            match found {
+                Some(to) => {
+                    let delta = to as i64 - line as i64;
+                    for end in ["start", "end"] {
+                        if let Some(l) = d["range"][end]["line"].as_u64() {
+                            d["range"][end]["line"] = json!((l as i64 + delta).max(0));
+                        }
+                    }
+                }
+                None => edit::mark_stale(d),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relocates_by_quoted_line() {
+        assert_eq!(
+            compiler_snippet("   12 |     int x = a + b;", Some("      |             ^")),
+            Some("    int x = a + b;".to_string())
+        );
+        assert_eq!(
+            compiler_snippet("    int x = a + b;", Some("            ^~~~~")),
+            Some("    int x = a + b;".to_string())
+        );
+        assert_eq!(compiler_snippet("1 error generated.", None), None);
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.c");
+        std::fs::write(
+            &file_path,
+            "#include <stdio.h>\n\nint main(void) {\n    int x = a + b;\n    return x;\n}\n",
+        )
+        .unwrap();
+        let uri = format!("file://{}", file_path.display());
+        let diag = |line: u64, fp: &str| {
+            json!({
+                "range": {
+                    "start": { "line": line, "character": 4 },
+                    "end": { "line": line, "character": 5 }
+                },
+                "severity": 1,
+                "message": "boom",
+                "data": { "fingerprint": fp }
+            })
+        };
+        let mut diags = HashMap::from([(
+            uri.clone(),
+            vec![
+                diag(1, "int x = a + b;"),
+                diag(4, "return x;"),
+                diag(2, "gone(away);"),
+            ],
+        )]);
+        relocate(&mut diags);
+        let list = &diags[&uri];
+        assert_eq!(list[0]["range"]["start"]["line"], json!(3));
+        assert_eq!(list[0]["range"]["end"]["line"], json!(3));
+        assert_eq!(list[1]["range"]["start"]["line"], json!(4));
+        assert_eq!(list[2]["message"], json!("[stale] boom"));
+
+        // the same line in another function far below is not where the report was
+        let far = format!(
+            "int main(void) {{\n    int y;\n}}\n{}int other(void) {{\n    int x = a + b;\n}}\n",
+            "\n".repeat(WINDOW)
+        );
+        std::fs::write(&file_path, far).unwrap();
+        let mut diags = HashMap::from([(uri.clone(), vec![diag(1, "int x = a + b;")])]);
+        relocate(&mut diags);
+        let list = &diags[&uri];
+        assert_eq!(list[0]["range"]["start"]["line"], json!(1));
+        assert_eq!(list[0]["message"], json!("[stale] boom"));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::IS_VERBOSE;
+use crate::SharedClients;
+use crate::SharedStore;
+use crate::config;
+use crate::log_parser::make_uri;
+use crate::lsp_diagnosis;
+use crate::runtime;
+use crate::source::LogSource;
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+// control lines of a stream, everything else is tool output
+// ex.
+// @clasangd run-start unit-tests
+// @clasangd diag {"file": "src/a.c", "line": 3, "column": 5, "severity": "error", "message": "boom"}
+// @clasangd run-end 1
+pub const MARKER: &str = "@clasangd ";
+
+// byte offset of the output of the last run, after its run-start marker.
+// the log before it belongs to older runs
+pub fn last_run_start(text: &str) -> Option<usize> {
+    let marker = format!("{}run-start", MARKER);
+    let mut pos = 0;
+    let mut last = None;
+    for line in text.split_inclusive('\n') {
+        pos += line.len();
+        if line.starts_with(&marker) {
+            last = Some(pos);
+        }
+    }
+    last
+}
+
+// diagnostics of one run on a stream, kept in the store as input "stream:<label>"
+struct Run {
+    input: String,
+    source: LogSource,
+    // output not parsed yet
+    pending: String,
+    carry: String,
+    json: HashMap<String, Vec<Value>>,
+    reset: bool,
+}
+
+impl Run {
+    fn new(label: &str, reset: bool) -> Self {
+        Run {
+            input: format!("stream:{}", label),
+            source: LogSource::file(label),
+            pending: String::new(),
+            carry: String::new(),
+            json: HashMap::new(),
+            reset,
+        }
+    }
+
+    // `end` commits the open report as well
+    async fn flush(&mut self, store: &SharedStore, clients: &SharedClients, end: bool) {
+        if !end && !self.reset && self.pending.is_empty() && self.json.is_empty() {
+            return;
+        }
+        let segment = std::mem::take(&mut self.carry) + &std::mem::take(&mut self.pending);
+        let (saved_uri, root_path, user_parsers, path_filter) = {
+            let st = store.lock().await;
+            (
+                st.saved_uri.clone(),
+                st.root_path.clone(),
+                st.user_parsers.clone(),
+                st.path_filter.clone(),
+            )
+        };
+        let (mut committed, mut provisional, carry) = lsp_diagnosis::parse_chunk(
+            &segment,
+            &saved_uri,
+            &root_path,
+            &user_parsers,
+            &path_filter,
+            &self.source,
+        );
+        if end {
+            for (uri, diags) in provisional.drain() {
+                committed.entry(uri).or_default().extend(diags);
+            }
+        } else {
+            self.carry = carry;
+        }
+        for (uri, diags) in self.json.drain() {
+            committed.entry(uri).or_default().extend(diags);
+        }
+        let reset = std::mem::take(&mut self.reset);
+        let read_again = reset || !committed.is_empty() || !provisional.is_empty();
+        let mut st = store.lock().await;
+        let mut uris = st.update_input(&self.input, committed, provisional, reset);
+        if read_again {
+            uris.extend(st.drop_restored(&self.input));
+        }
+        drop(st);
+        let mut uris: Vec<String> = uris.into_iter().collect();
+        uris.sort();
+        lsp_diagnosis::publish_uris(store.clone(), clients.clone(), &uris).await;
+    }
+}
+
+// a diagnostic sent as JSON, lines and columns are 1-based like in compiler output
+fn json_diagnostic(v: &Value, saved_uri: &str, root_path: &str) -> Option<(String, Value)> {
+    let message = v.get("message")?.as_str()?;
+    let file = v.get("file").or_else(|| v.get("uri"))?.as_str()?;
+    let uri = if file.starts_with("file://") {
+        file.to_string()
+    } else {
+        make_uri(file, saved_uri, root_path)
+    };
+    let num = |key: &str| v.get(key).and_then(|n| n.as_u64()).filter(|n| *n != 0);
+    let line = num("line").unwrap_or(1) - 1;
+    let col = num("column").unwrap_or(1) - 1;
+    let end_line = num("endLine").map(|l| l - 1).unwrap_or(line);
+    let end_col = num("endColumn").map(|c| c - 1).unwrap_or(col + 1);
+    let severity = match v.get("severity") {
+        Some(Value::String(s)) => config::severity_from_str(s),
+        Some(n) => n.as_u64().filter(|n| (1..=4).contains(n)),
+        None => None,
+    }
+    .unwrap_or(1);
+    let mut diag = json!({
+        "range": {
+            "start": { "line": line, "character": col },
+            "end": { "line": end_line, "character": end_col }
+        },
+        "severity": severity,
+        "source": v.get("source").and_then(|s| s.as_str()).unwrap_or("stream"),
+        "message": message
+    });
+    if let Some(code) = v.get("code") {
+        diag["code"] = code.clone();
+    }
+    Some((uri, diag))
+}
+
+// reads tool output and control lines until the writer goes away
+async fn read_stream<R>(reader: R, store: SharedStore, clients: SharedClients) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut reader = BufReader::new(reader);
+    // without run markers each connection is a run
+    let mut run = Run::new("stream", true);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).await? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        if let Some(cmd) = line.strip_prefix(MARKER) {
+            let cmd = cmd.trim();
+            let (verb, arg) = cmd.split_once(' ').unwrap_or((cmd, ""));
+            match verb {
+                "run-start" => {
+                    run.flush(&store, &clients, true).await;
+                    let label = if arg.is_empty() { "stream" } else { arg };
+                    run = Run::new(label, true);
+                }
+                "run-end" => {
+                    run.flush(&store, &clients, true).await;
+                    unsafe {
+                        if 0 < IS_VERBOSE {
+                            eprintln!("[clasangd] {} ended: {}", run.input, arg);
+                        }
+                    }
+                }
+                "diag" => {
+                    let (saved_uri, root_path) = {
+                        let st = store.lock().await;
+                        (st.saved_uri.clone(), st.root_path.clone())
+                    };
+                    match serde_json::from_str::<Value>(arg)
+                        .ok()
+                        .and_then(|v| json_diagnostic(&v, &saved_uri, &root_path))
+                    {
+                        Some((uri, diag)) => run.json.entry(uri).or_default().push(diag),
+                        None => eprintln!("[clasangd] invalid diagnostic: {}", arg),
+                    }
+                }
+                _ => eprintln!("[clasangd] unknown stream command: {}", cmd),
+            }
+        } else {
+            run.pending.push_str(&line);
+        }
+        // parse once the writer paused instead of after every line
+        if reader.buffer().is_empty() {
+            run.flush(&store, &clients, false).await;
+        }
+    }
+    run.flush(&store, &clients, true).await;
+    Ok(())
+}
+
+pub async fn listen_socket(path: &Path, store: SharedStore, clients: SharedClients) -> Result<()> {
+    let listener = runtime::bind_socket(path)?;
+    loop {
+        let (conn, _) = listener.accept().await?;
+        let store = store.clone();
+        let clients = clients.clone();
+        tokio::spawn(async move {
+            if let Err(e) = read_stream(conn, store, clients).await {
+                eprintln!("[clasangd] stream: {:#}", e);
+            }
+        });
+    }
+}
+
+pub async fn listen_fifo(path: &Path, store: SharedStore, clients: SharedClients) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        // opening for writing only succeeds while someone reads it, two readers would
+        // each get a part of the records
+        Ok(meta) if meta.file_type().is_fifo() => {
+            let reader = std::fs::OpenOptions::new()
+                .write(true)
+                .custom_flags(libc::O_NONBLOCK)
+                .open(path);
+            match reader {
+                Ok(_) => bail!("another server is reading {}", path.display()),
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to check {}", path.display()));
+                }
+            }
+        }
+        Ok(_) => bail!("{} exists and is not a fifo", path.display()),
+        Err(_) => {
+            let c_path = CString::new(path.as_os_str().as_bytes())?;
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("failed to create {}", path.display()));
+            }
+        }
+    }
+    // opened for writing as well, so it stays open between writers
+    let receiver = tokio::net::unix::pipe::OpenOptions::new()
+        .read_write(true)
+        .open_receiver(path)
+        .with_context(|| format!("failed to open {}", path.display()))?;
+    read_stream(receiver, store, clients).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_json_diagnostics() {
+        let v = json!({
+            "file": "file:///src/a.c",
+            "line": 3,
+            "column": 5,
+            "severity": "warning",
+            "message": "unused variable",
+            "code": "W1"
+        });
+        let (uri, diag) = json_diagnostic(&v, "file:///tmp/dummy.c", "/").unwrap();
+        assert_eq!(uri, "file:///src/a.c");
+        assert_eq!(diag["range"]["start"], json!({ "line": 2, "character": 4 }));
+        assert_eq!(diag["range"]["end"], json!({ "line": 2, "character": 5 }));
+        assert_eq!(diag["severity"], json!(2));
+        assert_eq!(diag["source"], json!("stream"));
+        assert_eq!(diag["code"], json!("W1"));
+
+        assert!(json_diagnostic(&json!({ "file": "a.c" }), "", "/").is_none());
+    }
+}
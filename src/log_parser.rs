@@ -1,17 +1,100 @@
 // use crate::prelude::*;
+use crate::config::{Matcher, UserParser};
+use crate::errorformat;
+use crate::file_index::{self, resolve_path};
+use crate::fingerprint;
+use crate::path_filter::PathFilter;
+use crate::symbolize;
 use regex::Regex;
 use serde_json::{Value, json};
-use std::collections::{HashMap, HashSet, VecDeque};
-use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
-pub fn parse_diagnostics(text: &str, uri: &str, root: &str) -> HashMap<String, Vec<Value>> {
+// names accepted by a source's parser list, next to the names of user parsers
+pub const BUILTIN_PARSERS: &[&str] = &[
+    "oneline",
+    "msbuild",
+    "block",
+    "sanitizer",
+    "stacktrace",
+    "traceback",
+    "gtest",
+    "catch2",
+];
+
+pub fn parse_diagnostics(
+    text: &str,
+    uri: &str,
+    root: &str,
+    user_parsers: &[UserParser],
+) -> HashMap<String, Vec<Value>> {
+    parse_selected(text, uri, root, user_parsers, &PathFilter::default(), &[])
+}
+
+// runs only the named parsers, or all of them when `only` is empty.
+// diagnostics in files the filter doesn't allow are dropped
+pub fn parse_selected(
+    text: &str,
+    uri: &str,
+    root: &str,
+    user_parsers: &[UserParser],
+    filter: &PathFilter,
+    only: &[String],
+) -> HashMap<String, Vec<Value>> {
+    let _scope = file_index::parse_scope();
+    let enabled = |name: &str| only.is_empty() || only.iter().any(|o| o == name);
     let mut out: HashMap<String, Vec<Value>> = HashMap::new();
-    parse_oneline(text, uri, root, &mut out);
-    parse_san_error(text, uri, root, &mut out);
-    parse_stacktrace(text, uri, root, &mut out);
-    parse_traceback(text, uri, root, &mut out);
+    if enabled("oneline") {
+        parse_oneline(text, uri, root, &mut out);
+    }
+    if enabled("msbuild") {
+        parse_msbuild(text, uri, root, &mut out);
+    }
+    if enabled("block") {
+        parse_block_header(text, uri, root, &mut out);
+    }
+    if enabled("sanitizer") {
+        parse_san_error(text, uri, root, filter, &mut out);
+    }
+    if enabled("stacktrace") {
+        parse_stacktrace(text, uri, root, filter, &mut out);
+    }
+    if enabled("traceback") {
+        parse_traceback(text, uri, root, filter, &mut out);
+    }
+    if enabled("gtest") {
+        parse_gtest(text, uri, root, &mut out);
+    }
+    if enabled("catch2") {
+        parse_catch2(text, uri, root, &mut out);
+    }
+    for parser in user_parsers.iter().filter(|p| enabled(&p.name)) {
+        parse_user(text, uri, root, parser, &mut out);
+    }
+    if filter.is_active() {
+        out.retain(|uri, _| filter.allows_uri(uri));
+    }
     out
 }
+// byte offset of the last line which starts a new report. every report before it is complete,
+// so a log can be committed up to here and the rest parsed again when more lines arrive
+pub fn last_record_start(text: &str, user_parsers: &[UserParser]) -> usize {
+    let re_start = Regex::new(
+        r"^(?:==\d+==ERROR:|Traceback \(most recent call last\):|Exception in thread |\[ RUN\s+\]|={20,}$|[^\s:(][^:(]*:\d+:\d+(?:-\d+)?:|\s*(?:\d+>)?[^\s(][^(]*?\(\d+(?:,\d+)*\)\s*:)",
+    )
+    .expect("invalid regex");
+
+    let mut last = 0;
+    let mut pos = 0;
+    for line in text.split_inclusive('\n') {
+        let l = line.trim_end_matches(['\r', '\n']);
+        if re_start.is_match(l) || user_parsers.iter().any(|p| p.starts_record(l)) {
+            last = pos;
+        }
+        pos += line.len();
+    }
+    last
+}
 pub fn parse_oneline(
     text: &str,
     saved_uri: &str,
@@ -34,24 +117,242 @@ pub fn parse_oneline(
             _ => 1,
         };
         let msg = cap[5].to_string();
+        if is_header_only(&msg) {
+            // the message follows on the next lines, see parse_block_header
+            continue;
+        }
 
-        let diag = json!({
+        // the sanitizers report at runtime, everything else comes from the compiler
+        let source = if &cap[4] == "runtime error" {
+            "ubsan"
+        } else {
+            "compiler"
+        };
+        let mut diag = json!({
             "range": {
                 "start": { "line": line, "character": col },
                 "end":   { "line": line, "character": col + 1 }
             },
             "severity": sev,
-            "source": "ubsan/asan",
+            "source": source,
             "message": msg
         });
+        // clang and gcc quote the source line below the message
+        let mut below = text[cap.get(0).map_or(0, |m| m.end())..].lines().skip(1);
+        if let Some(code) = below.next()
+            && let Some(fp) = fingerprint::compiler_snippet(code, below.next())
+        {
+            diag["data"] = json!({ "fingerprint": fp });
+        }
 
         out.entry(uri).or_default().push(diag);
     }
 }
+pub fn parse_msbuild(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // ex. Program.cs(12,5): error CS1002: ; expected [/path/to/app.csproj]
+    // ex. file.cpp(10): warning C4244: 'argument': conversion from 'double' to 'int'
+    // ex. 1>file.cpp(10,5,10,9): error: message  ... clang-cl / parallel MSBuild node prefix
+    let re = Regex::new(
+        r"(?m)^\s*(?:\d+>)?(?P<file>[^\s(][^(]*?)\((?P<line>\d+)(?:,(?P<col>\d+)(?:,(?P<endline>\d+),(?P<endcol>\d+))?)?\)\s*:\s*(?P<sev>fatal error|error|warning|note|info|message)(?:\s+(?P<code>[A-Za-z]+\d+))?\s*:\s*(?P<msg>.*?)(?:\s+\[[^\]]*\])?\s*$",
+    )
+    .expect("invalid regex");
+
+    for cap in re.captures_iter(text) {
+        let file = cap["file"].trim().replace('\\', "/");
+        let uri = make_uri(&file, saved_uri, root_path);
+        let line = cap["line"].parse::<u64>().unwrap_or(1).saturating_sub(1);
+        let col = cap
+            .name("col")
+            .and_then(|m| m.as_str().parse::<u64>().ok())
+            .unwrap_or(1)
+            .saturating_sub(1);
+        // end column is 1-based inclusive, which is the same number as 0-based exclusive
+        let (end_line, end_col) = match (cap.name("endline"), cap.name("endcol")) {
+            (Some(el), Some(ec)) => (
+                el.as_str().parse::<u64>().unwrap_or(1).saturating_sub(1),
+                ec.as_str().parse::<u64>().unwrap_or(0),
+            ),
+            _ => (line, col + 1),
+        };
+        let end_col = if end_line == line {
+            end_col.max(col + 1)
+        } else {
+            end_col
+        };
+        let sev = match &cap["sev"] {
+            "note" | "info" | "message" => 3,
+            "warning" => 2,
+            _ => 1,
+        };
+
+        let mut diag = json!({
+            "range": {
+                "start": { "line": line, "character": col },
+                "end":   { "line": end_line, "character": end_col }
+            },
+            "severity": sev,
+            "source": "msbuild",
+            "message": cap["msg"].to_string()
+        });
+        if let Some(code) = cap.name("code") {
+            diag["code"] = json!(code.as_str());
+        }
+
+        out.entry(uri).or_default().push(diag);
+    }
+}
+pub fn parse_block_header(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // gfortran puts the location on its own line and the message a few lines later
+    // ex.
+    // test.f90:3:10:
+    //
+    //     3 |   x = y +
+    //       |          1
+    // Error: Syntax error in expression at (1)
+    //
+    // ghc puts the message in an indented body below the header
+    // ex.
+    // src/Main.hs:3:5: error: [GHC-88464]
+    //     Variable not in scope: foo :: IO ()
+    //   |
+    // 3 |     foo
+    //   |     ^^^
+    let re_header = Regex::new(
+        r"^(?P<file>[^\s:][^:]*?):(?:(?P<line>\d+):(?P<col>\d+)(?:-(?P<endcol>\d+))?|\((?P<sline>\d+),(?P<scol>\d+)\)-\((?P<eline>\d+),(?P<ecol>\d+)\)):\s*(?:(?P<sev>error|warning|note|info)\s*:\s*(?P<rest>.*))?$",
+    )
+    .expect("invalid regex");
+    let re_message = Regex::new(r"^\s*(Fatal Error|Error|Warning|Note|Info)\s*:\s*(.*)$")
+        .expect("invalid regex");
+    let re_snippet = Regex::new(r"^\s*\d*\s*\|").expect("invalid regex");
+    const MAX_GAP: usize = 10;
+
+    let lines: Vec<&str> = text.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(cap) = re_header.captures(line) else {
+            continue;
+        };
+        let num = |name: &str| {
+            cap.name(name)
+                .and_then(|m| m.as_str().parse::<u64>().ok())
+                .map(|n| n.saturating_sub(1))
+        };
+        let (start_line, start_col, end_line, end_col) =
+            if let (Some(l), Some(c)) = (num("line"), num("col")) {
+                let ec = num("endcol").map(|e| e + 1).unwrap_or(c + 1);
+                (l, c, l, ec.max(c + 1))
+            } else {
+                (
+                    num("sline").unwrap_or(0),
+                    num("scol").unwrap_or(0),
+                    num("eline").unwrap_or(0),
+                    num("ecol").map(|e| e + 1).unwrap_or(0),
+                )
+            };
+
+        let (sev, source, code, msg) = match cap.name("sev") {
+            None => {
+                // gfortran: search the message below the snippet
+                let found = lines
+                    .iter()
+                    .skip(i + 1)
+                    .take(MAX_GAP)
+                    .take_while(|l| !re_header.is_match(l))
+                    .find_map(|l| re_message.captures(l));
+                let Some(m) = found else {
+                    continue;
+                };
+                let sev = match &m[1] {
+                    "Warning" => 2,
+                    "Note" | "Info" => 3,
+                    _ => 1,
+                };
+                (sev, "gfortran", None, m[2].to_string())
+            }
+            Some(sev) => {
+                let rest = cap.name("rest").map(|m| m.as_str().trim()).unwrap_or("");
+                if !is_header_only(rest) {
+                    // the whole message is on this line, parse_oneline already took it
+                    continue;
+                }
+                let body: Vec<&str> = lines
+                    .iter()
+                    .skip(i + 1)
+                    .take_while(|l| l.starts_with(char::is_whitespace))
+                    .filter(|l| !re_snippet.is_match(l))
+                    .map(|l| l.trim().trim_start_matches('•').trim())
+                    .filter(|l| !l.is_empty())
+                    .collect();
+                if body.is_empty() {
+                    continue;
+                }
+                let sev = match sev.as_str() {
+                    "warning" => 2,
+                    "note" | "info" => 3,
+                    _ => 1,
+                };
+                // the error code first, then the warning flags
+                let tags = header_tags(rest).unwrap_or_default();
+                let code = tags.first().map(|c| c.to_string());
+                let mut msg = body.join("\n");
+                for flag in tags.iter().skip(1) {
+                    msg.push_str(&format!(" [{}]", flag));
+                }
+                (sev, "ghc", code, msg)
+            }
+        };
+
+        let uri = make_uri(&cap["file"], saved_uri, root_path);
+        let mut diag = json!({
+            "range": {
+                "start": { "line": start_line, "character": start_col },
+                "end":   { "line": end_line, "character": end_col }
+            },
+            "severity": sev,
+            "source": source,
+            "message": msg
+        });
+        if let Some(code) = code {
+            diag["code"] = json!(code);
+        }
+
+        out.entry(uri).or_default().push(diag);
+    }
+}
+
+// the tags of a header like "file:1:2: error:" or "file:1:2: warning: [GHC-40910] [-Wunused-matches]"
+// whose message is on the next lines, None when the message is on the header line
+fn header_tags(msg: &str) -> Option<Vec<&str>> {
+    let mut tags = Vec::new();
+    let mut rest = msg.trim();
+    while !rest.is_empty() {
+        let (tag, after) = rest.strip_prefix('[')?.split_once(']')?;
+        if tag.contains('[') {
+            return None;
+        }
+        tags.push(tag);
+        rest = after.trim_start();
+    }
+    Some(tags)
+}
+
+fn is_header_only(msg: &str) -> bool {
+    header_tags(msg).is_some()
+}
 pub fn parse_san_error(
     text: &str,
     saved_uri: &str,
     root_path: &str,
+    filter: &PathFilter,
     out: &mut HashMap<String, Vec<Value>>,
 ) {
     // ex.
@@ -84,30 +385,81 @@ pub fn parse_san_error(
         Regex::new(r"^==\d+==ERROR:\s+([A-Za-z]+Sanitizer):\s*(.*)$").expect("invalid regex");
     let re_frame =
         Regex::new(r"^\s*#(\d+)\s+\S+\s+in\s+\S+\s+(/[^:]+):(\d+):(\d+)").expect("invalid regex");
+    // without a symbolizer: #4 0x5602fa42c094 in _start (/tmp/c_test+0x2c094)
+    let re_module_frame = Regex::new(
+        r"^\s*#(\d+)\s+0x[0-9a-fA-F]+\s+(?:in\s+\S+\s+)?\(([^()+]+)\+0x([0-9a-fA-F]+)\)",
+    )
+    .expect("invalid regex");
     let mut current_kind: Option<String> = None;
     let mut current_msg: Option<String> = None;
+    // with a filter, the first frame it allows is reported and those above it are kept
+    let mut chosen = false;
+    let mut skipped: Vec<Value> = Vec::new();
 
-    for line in text.lines() {
-        if let Some(cap) = re_san.captures(line) {
+    for text_line in text.lines() {
+        if let Some(cap) = re_san.captures(text_line) {
             current_kind = Some(cap[1].to_string());
             current_msg = Some(cap[2].to_string());
             continue;
         }
-        if let (Some(kind), Some(msg), Some(cap)) = (
-            current_kind.as_ref(),
-            current_msg.as_ref(),
-            re_frame.captures(line),
-        ) {
-            let frame_num: u32 = cap[1].parse().unwrap_or(999);
-            if frame_num != 1 {
+        let (Some(kind), Some(msg)) = (current_kind.as_ref(), current_msg.as_ref()) else {
+            continue;
+        };
+        let Some((cap, module)) = re_frame
+            .captures(text_line)
+            .map(|c| (c, false))
+            .or_else(|| re_module_frame.captures(text_line).map(|c| (c, true)))
+        else {
+            continue;
+        };
+        let frame_num: u32 = cap[1].parse().unwrap_or(999);
+        if frame_num == 0 {
+            chosen = false;
+            skipped.clear();
+        }
+        let wanted = if filter.is_active() {
+            !chosen
+        } else {
+            frame_num == 1
+        };
+        if !wanted {
+            continue;
+        }
+        let location = if module {
+            let offset = u64::from_str_radix(&cap[3], 16).unwrap_or(0);
+            symbolize::locate(cap[2].trim(), offset, frame_num > 0)
+        } else {
+            Some((
+                cap[2].to_string(),
+                cap[3].parse::<u64>().unwrap_or(1),
+                cap[4].parse::<u64>().unwrap_or(1),
+            ))
+        };
+        if let Some((file, line, col)) = location {
+            let line = line.saturating_sub(1);
+            let col = col.saturating_sub(1);
+            // frame #0 is usually the sanitizer's interceptor, only reported when it is user code
+            if frame_num == 0
+                && !resolve_path(&file, root_path)
+                    .is_some_and(|p| filter.in_workspace(&p) && filter.allows(&p))
+            {
                 continue;
             }
-            let uri = make_uri(&cap[2], saved_uri, root_path);
-            let line = cap[3].parse::<u64>().unwrap_or(1).saturating_sub(1);
-            let col = cap[4].parse::<u64>().unwrap_or(1).saturating_sub(1);
+            let uri = if filter.is_active() {
+                match filter_frame(&file, line, saved_uri, root_path, filter, text_line.trim()) {
+                    Ok(uri) => uri,
+                    Err(related) => {
+                        skipped.push(related);
+                        continue;
+                    }
+                }
+            } else {
+                make_uri(&file, saved_uri, root_path)
+            };
+            chosen = true;
             let sev = 1; // 1=Error,2=Warning
 
-            let diag = json!({
+            let mut diag = json!({
                 "range": {
                     "start": { "line": line, "character": col },
                     "end":   { "line": line, "character": col + 1 }
@@ -116,83 +468,292 @@ pub fn parse_san_error(
                 "source": format!("sanitizer/{}",kind),
                 "message": msg
             });
+            if !skipped.is_empty() {
+                diag["relatedInformation"] = json!(std::mem::take(&mut skipped));
+            }
 
             out.entry(uri).or_default().push(diag);
         };
     }
 }
 
-pub fn make_uri(p: &str, uri: &str, root: &str) -> String {
-    if let Ok(path) = std::fs::canonicalize(p) {
-        return format!("file://{}", path.display());
-    }
+pub fn parse_gtest(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // ex.
+    // [ RUN      ] FooTest.Bar
+    // /path/to/test.cpp:12: Failure
+    // Expected equality of these values:
+    //   x
+    //     Which is: 1
+    //   2
+    // [  FAILED  ] FooTest.Bar (0 ms)
+    let re_run = Regex::new(r"^\[ RUN\s+\] (.+)$").expect("invalid regex");
+    let re_failure = Regex::new(r"^(.+?):(\d+):\s*Failure\s*(.*)$").expect("invalid regex");
 
-    let root_relative = format!("{}/{}", root, p);
-    if let Ok(path) = std::fs::canonicalize(&root_relative) {
-        return format!("file://{}", path.display());
-    }
+    let lines: Vec<&str> = text.lines().collect();
+    let mut test_name: Option<&str> = None;
 
-    if let Some(filename) = Path::new(p).file_name().and_then(|f| f.to_str()) {
-        if let Some(found) = find_file_bfs(root, filename) {
-            return format!("file://{}", found.display());
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(cap) = re_run.captures(line) {
+            test_name = cap.get(1).map(|m| m.as_str().trim());
+            continue;
         }
-    }
+        if line.starts_with("[       OK ]") || line.starts_with("[  FAILED  ]") {
+            test_name = None;
+            continue;
+        }
+        let Some(test) = test_name else {
+            continue;
+        };
+        let Some(cap) = re_failure.captures(line) else {
+            continue;
+        };
+        let line_num = cap[2].parse::<u64>().unwrap_or(1).saturating_sub(1);
+        let mut body: Vec<&str> = cap
+            .get(3)
+            .map(|m| m.as_str())
+            .filter(|m| !m.is_empty())
+            .into_iter()
+            .collect();
+        body.extend(
+            lines
+                .iter()
+                .skip(i + 1)
+                .take_while(|l| !l.starts_with('[') && !re_failure.is_match(l))
+                .copied(),
+        );
+        let detail = body.join("\n");
+        let uri = make_uri(&cap[1], saved_uri, root_path);
 
-    uri.to_string()
-}
+        let diag = json!({
+            "range": {
+                "start": { "line": line_num, "character": 0 },
+                "end": { "line": line_num, "character": 1 }
+            },
+            "severity": 1,
+            "source": "gtest",
+            "message": format!("{}: {}", test, detail.trim_end())
+        });
 
-fn find_file_bfs(root: &str, filename: &str) -> Option<PathBuf> {
-    let root_path = Path::new(root);
-    if !root_path.is_dir() {
-        return None;
+        out.entry(uri).or_default().push(diag);
     }
+}
+pub fn parse_catch2(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // ex.
+    // -------------------------------------------------------------------------------
+    // Factorials are computed
+    // -------------------------------------------------------------------------------
+    // test.cpp:15
+    // ...............................................................................
+    //
+    // test.cpp:20: FAILED:
+    //   REQUIRE( Factorial(0) == 1 )
+    // with expansion:
+    //   0 == 1
+    //
+    // ===============================================================================
+    let re_failed = Regex::new(r"^(.+?):(\d+):\s*FAILED:\s*$").expect("invalid regex");
+    let re_separator = Regex::new(r"^(?:-{20,}|={20,}|\.{20,})$").expect("invalid regex");
 
-    let mut queue = VecDeque::new();
-    queue.push_back(root_path.to_path_buf());
-    let mut visited = HashSet::new();
+    let lines: Vec<&str> = text.lines().collect();
+    let mut test_name: Option<String> = None;
+    let mut skip_to = 0;
 
-    while let Some(dir) = queue.pop_front() {
-        if !visited.insert(dir.clone()) {
+    for (i, line) in lines.iter().enumerate() {
+        if i < skip_to {
             continue;
         }
+        if line.starts_with("-----") && re_separator.is_match(line) {
+            // test case name (and sections) between two dashed lines
+            let names: Vec<&str> = lines
+                .iter()
+                .skip(i + 1)
+                .take_while(|l| !re_separator.is_match(l))
+                .map(|l| l.trim())
+                .collect();
+            if !names.is_empty() && lines.get(i + 1 + names.len()).is_some() {
+                test_name = Some(names.join(" / "));
+                skip_to = i + names.len() + 2;
+            }
+            continue;
+        }
+        let Some(cap) = re_failed.captures(line) else {
+            continue;
+        };
+        let line_num = cap[2].parse::<u64>().unwrap_or(1).saturating_sub(1);
+        let body: Vec<&str> = lines
+            .iter()
+            .skip(i + 1)
+            .take_while(|l| !re_separator.is_match(l) && !re_failed.is_match(l))
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty())
+            .collect();
+        let detail = body.join("\n");
+        let message = match &test_name {
+            Some(test) => format!("{}: {}", test, detail),
+            None => detail,
+        };
+        let uri = make_uri(&cap[1], saved_uri, root_path);
 
-        if let Ok(entries) = std::fs::read_dir(&dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
+        let diag = json!({
+            "range": {
+                "start": { "line": line_num, "character": 0 },
+                "end": { "line": line_num, "character": 1 }
+            },
+            "severity": 1,
+            "source": "catch2",
+            "message": message
+        });
 
-                if path.is_file() {
-                    if let Some(name) = path.file_name() {
-                        if name == filename {
-                            return Some(path);
-                        }
-                    }
-                } else if path.is_dir() && !is_ignore_dir(&path) {
-                    queue.push_back(path);
-                }
+        out.entry(uri).or_default().push(diag);
+    }
+}
+pub fn parse_user(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    parser: &UserParser,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    let re = match &parser.matcher {
+        Matcher::Regex(re) => re,
+        Matcher::ErrorFormat(efm) => {
+            errorformat::parse_errorformat(text, saved_uri, root_path, efm, parser, out);
+            return;
+        }
+    };
+    let blocks: Vec<String> = match &parser.start {
+        Some(start) => split_blocks(text, start, parser.end.as_ref()),
+        None => vec![text.to_string()],
+    };
+
+    for block in &blocks {
+        for cap in re.captures_iter(block) {
+            let num = |name: &str| {
+                cap.name(name)
+                    .and_then(|m| m.as_str().trim().parse::<u64>().ok())
+            };
+            let uri = match cap.name("file") {
+                Some(f) => make_uri(f.as_str().trim(), saved_uri, root_path),
+                None => saved_uri.to_string(),
+            };
+            let line = num("line").unwrap_or(1).saturating_sub(1);
+            let col = num("col").unwrap_or(1).saturating_sub(1);
+            let end_line = num("endline").map(|l| l.saturating_sub(1)).unwrap_or(line);
+            let end_col = num("endcol").unwrap_or(col + 1);
+            let end_col = if end_line == line {
+                end_col.max(col + 1)
+            } else {
+                end_col
+            };
+            let sev = parser.severity_of(cap.name("severity").map(|m| m.as_str()));
+            let msg = cap
+                .name("message")
+                .map(|m| m.as_str().trim().to_string())
+                .unwrap_or_else(|| parser.name.clone());
+
+            let mut diag = json!({
+                "range": {
+                    "start": { "line": line, "character": col },
+                    "end":   { "line": end_line, "character": end_col }
+                },
+                "severity": sev,
+                "source": parser.source,
+                "message": msg
+            });
+            if let Some(code) = cap.name("code") {
+                diag["code"] = json!(code.as_str());
             }
+
+            out.entry(uri).or_default().push(diag);
         }
     }
+}
+
+// a block runs from a `start` line to an `end` line, or without `end` to the next start or blank line
+fn split_blocks(text: &str, start: &Regex, end: Option<&Regex>) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<String> = None;
 
-    None
+    for line in text.lines() {
+        if start.is_match(line) {
+            if let Some(block) = current.take() {
+                blocks.push(block);
+            }
+            current = Some(String::new());
+        }
+        let Some(block) = current.as_mut() else {
+            continue;
+        };
+        if end.is_none() && line.trim().is_empty() {
+            blocks.extend(current.take());
+            continue;
+        }
+        block.push_str(line);
+        block.push('\n');
+        if end.is_some_and(|e| e.is_match(line)) {
+            blocks.extend(current.take());
+        }
+    }
+    blocks.extend(current);
+    blocks
 }
 
-fn is_ignore_dir(path: &Path) -> bool {
-    path.file_name()
-        .and_then(|n| n.to_str())
-        .map(|n| {
-            n.starts_with('.')
-                || n == "target"
-                || n == "node_modules"
-                || n == "build"
-                || n == "dist"
-        })
-        .unwrap_or(false)
+pub fn make_uri(p: &str, uri: &str, root: &str) -> String {
+    match resolve_path(p, root) {
+        Some(path) => format!("file://{}", path.display()),
+        None => uri.to_string(),
+    }
+}
+
+// the uri of a stack frame the filter allows, or else the frame as related information
+// for the diagnostic of the first frame which is allowed
+fn filter_frame(
+    file: &str,
+    line: u64,
+    saved_uri: &str,
+    root_path: &str,
+    filter: &PathFilter,
+    message: &str,
+) -> Result<String, Value> {
+    let path = resolve_path(file, root_path);
+    let shown = path.as_deref().unwrap_or(Path::new(file));
+    if filter.allows(shown) {
+        return Ok(path
+            .map(|p| format!("file://{}", p.display()))
+            .unwrap_or_else(|| saved_uri.to_string()));
+    }
+    let shown = if shown.is_absolute() {
+        shown.to_path_buf()
+    } else {
+        Path::new(root_path).join(shown)
+    };
+    Err(json!({
+        "location": {
+            "uri": format!("file://{}", shown.display()),
+            "range": {
+                "start": { "line": line, "character": 0 },
+                "end": { "line": line, "character": 0 }
+            }
+        },
+        "message": message
+    }))
 }
 
 pub fn parse_stacktrace(
     text: &str,
     saved_uri: &str,
     root_path: &str,
+    filter: &PathFilter,
     out: &mut HashMap<String, Vec<Value>>,
 ) {
     // Exception in thread "main" java.lang.ArrayIndexOutOfBoundsException: Index 0 out of bounds for length 0
@@ -203,34 +764,49 @@ pub fn parse_stacktrace(
     let re_at = Regex::new(r"^\s+at\s+.+?\((.+?):(\d+)\)").expect("invalid regex");
 
     let mut current_exception: Option<String> = None;
+    let mut skipped: Vec<Value> = Vec::new();
 
     for line in text.lines() {
         if let Some(cap) = re_exception.captures(line) {
             let message = cap.get(1).map(|m| m.as_str()).unwrap_or("Runtime error");
             current_exception = Some(message.to_string());
+            skipped.clear();
             continue;
         }
 
-        if let Some(ref exc_msg) = current_exception {
-            if let Some(cap) = re_at.captures(line) {
-                let file = &cap[1];
-                let line_num = cap[2].parse::<u64>().unwrap_or(1).saturating_sub(1);
-                let uri = make_uri(file, saved_uri, root_path);
-
-                let diag = json!({
-                    "range": {
-                        "start": { "line": line_num, "character": 0 },
-                        "end": { "line": line_num, "character": 1 }
-                    },
-                    "severity": 1,
-                    "source": "runtime",
-                    "message": exc_msg
-                });
-
-                out.entry(uri).or_default().push(diag);
-                current_exception = None;
-                continue;
+        if let Some(ref exc_msg) = current_exception
+            && let Some(cap) = re_at.captures(line)
+        {
+            let file = &cap[1];
+            let line_num = cap[2].parse::<u64>().unwrap_or(1).saturating_sub(1);
+            let uri = if filter.is_active() {
+                match filter_frame(file, line_num, saved_uri, root_path, filter, line.trim()) {
+                    Ok(uri) => uri,
+                    Err(related) => {
+                        skipped.push(related);
+                        continue;
+                    }
+                }
+            } else {
+                make_uri(file, saved_uri, root_path)
+            };
+
+            let mut diag = json!({
+                "range": {
+                    "start": { "line": line_num, "character": 0 },
+                    "end": { "line": line_num, "character": 1 }
+                },
+                "severity": 1,
+                "source": "runtime",
+                "message": exc_msg
+            });
+            if !skipped.is_empty() {
+                diag["relatedInformation"] = json!(std::mem::take(&mut skipped));
             }
+
+            out.entry(uri).or_default().push(diag);
+            current_exception = None;
+            continue;
         }
     }
 }
@@ -238,6 +814,7 @@ pub fn parse_traceback(
     text: &str,
     saved_uri: &str,
     root_path: &str,
+    filter: &PathFilter,
     out: &mut HashMap<String, Vec<Value>>,
 ) {
     // Traceback (most recent call last):
@@ -259,41 +836,75 @@ pub fn parse_traceback(
 
     let mut current_exception: Option<String> = None;
     let mut sev = 1;
+    // only the innermost frame of each file is reported per traceback
+    let mut seen_uris: HashSet<String> = HashSet::new();
+    // frames the filter skipped below the innermost one reported
+    let mut skipped: Vec<Value> = Vec::new();
 
+    // the line below a frame quotes its source
+    let mut below: Option<&str> = None;
     for line in text.lines().rev() {
+        let quoted = below.replace(line);
         if let Some(cap) = re_error.captures(line) {
             let message = cap
                 .name("msg")
                 .map(|m| m.as_str())
                 .unwrap_or("Runtime error");
             current_exception = Some(message.to_string());
+            sev = 1;
+            seen_uris.clear();
+            skipped.clear();
             continue;
         }
-        if let Some(ref exc_msg) = current_exception {
-            if let Some(cap) = re_at.captures(line) {
-                let file = &cap[1];
-                let line_num = cap[2].parse::<u64>().unwrap_or(1).saturating_sub(1);
-                let uri = make_uri(file, saved_uri, root_path);
-                let location = cap
-                    .get(3)
-                    .map(|m| format!(" in {}", m.as_str()))
-                    .unwrap_or_default();
-
-                let diag = json!({
-                    "range": {
-                        "start": { "line": line_num, "character": 0 },
-                        "end": { "line": line_num, "character": 1 }
-                    },
-                    "severity": sev,
-                    "source": "runtime",
-                    "message": format!("{exc_msg}{location}")
-                });
-                sev = 2;
-                out.entry(uri).or_default().push(diag);
+        if line.starts_with("Traceback") {
+            current_exception = None;
+            continue;
+        }
+        if let Some(ref exc_msg) = current_exception
+            && let Some(cap) = re_at.captures(line)
+        {
+            let file = &cap[1];
+            let line_num = cap[2].parse::<u64>().unwrap_or(1).saturating_sub(1);
+            let uri = if filter.is_active() {
+                match filter_frame(file, line_num, saved_uri, root_path, filter, line.trim()) {
+                    Ok(uri) => uri,
+                    Err(related) => {
+                        if sev == 1 {
+                            skipped.push(related);
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                make_uri(file, saved_uri, root_path)
+            };
+            if !seen_uris.insert(uri.clone()) {
                 continue;
             }
-        } else {
-            sev = 1;
+            let location = cap
+                .get(3)
+                .map(|m| format!(" in {}", m.as_str()))
+                .unwrap_or_default();
+
+            let mut diag = json!({
+                "range": {
+                    "start": { "line": line_num, "character": 0 },
+                    "end": { "line": line_num, "character": 1 }
+                },
+                "severity": sev,
+                "source": "runtime",
+                "message": format!("{exc_msg}{location}")
+            });
+            if let Some(code) = quoted
+                .filter(|q| !re_at.is_match(q) && !re_error.is_match(q) && q.starts_with("    "))
+            {
+                diag["data"] = json!({ "fingerprint": code.trim() });
+            }
+            if !skipped.is_empty() {
+                diag["relatedInformation"] = json!(std::mem::take(&mut skipped));
+            }
+            sev = 2;
+            out.entry(uri).or_default().push(diag);
         }
     }
 }
@@ -302,6 +913,55 @@ pub fn parse_traceback(
 mod tests {
     use super::*;
 
+    #[test]
+    fn reports_innermost_frame_per_traceback() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("fact.py");
+        std::fs::write(&file_path, "# python test file").unwrap();
+
+        // a recursion puts several frames on one file, a chained traceback starts over
+        let log = format!(
+            r#"Traceback (most recent call last):
+  File "{file}", line 2, in fact
+    return n * fact(n - 1)
+  File "{file}", line 2, in fact
+    return n * fact(n - 1)
+  File "{file}", line 1, in fact
+    def fact(n): 1 / 0
+ZeroDivisionError: division by zero
+
+During handling of the above exception, another exception occurred:
+
+Traceback (most recent call last):
+  File "{file}", line 9, in <module>
+    raise ValueError("bad")
+ValueError: bad
+"#,
+            file = file_path.display()
+        );
+
+        let mut out = HashMap::new();
+        parse_traceback(
+            &log,
+            "file:///tmp/dummy.py",
+            temp_dir.path().to_str().unwrap(),
+            &PathFilter::default(),
+            &mut out,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = &out[&uri];
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(8));
+        assert_eq!(diags[0]["message"], json!("ValueError: bad in <module>"));
+        assert_eq!(diags[1]["range"]["start"]["line"], json!(0));
+        assert_eq!(
+            diags[1]["message"],
+            json!("ZeroDivisionError: division by zero in fact")
+        );
+        assert!(diags.iter().all(|d| d["severity"] == json!(1)));
+    }
+
     #[test]
     fn parses_python_traceback() {
         let temp_dir = tempfile::tempdir().unwrap();
@@ -327,6 +987,7 @@ IndexError: list index out of range
             &log,
             "file:///tmp/dummy.py",
             temp_dir.path().to_str().unwrap(),
+            &PathFilter::default(),
             &mut out,
         );
 
@@ -344,4 +1005,281 @@ IndexError: list index out of range
             json!("IndexError: list index out of range in b_tree_insert_nonfull")
         );
     }
+
+    #[test]
+    fn traceback_points_at_my_code() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("b_tree.py");
+        std::fs::write(&file_path, "# python test file").unwrap();
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+
+        // the library frame is only related information
+        let log = format!(
+            r#"Traceback (most recent call last):
+  File "{file}", line 66, in <module>
+    tree.b_tree_insert(i)
+  File "/usr/lib/python3/site-packages/sortedcontainers/sortedlist.py", line 260, in add
+    self._lists.append([value])
+TypeError: '<' not supported
+"#,
+            file = file_path.display()
+        );
+        let root = temp_dir.path().to_str().unwrap();
+        let rules = crate::config::PathsConfig {
+            just_my_code: true,
+            ..Default::default()
+        };
+        let filter = PathFilter::new(&rules, &[root.to_string()]).unwrap();
+        let mut out = HashMap::new();
+        parse_traceback(&log, "file:///tmp/dummy.py", root, &filter, &mut out);
+        assert_eq!(out.len(), 1);
+        let diag = &out[&uri][0];
+        assert_eq!(diag["range"]["start"]["line"], json!(65));
+        assert_eq!(diag["severity"], json!(1));
+        assert_eq!(
+            diag["relatedInformation"][0]["location"]["uri"],
+            json!("file:///usr/lib/python3/site-packages/sortedcontainers/sortedlist.py")
+        );
+    }
+
+    #[test]
+    fn fingerprints_traceback_frame() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("calc.py");
+        std::fs::write(&file_path, "# python test file").unwrap();
+
+        let log = format!(
+            r#"Traceback (most recent call last):
+  File "{file}", line 3, in div
+    return a / b
+           ~~^~~
+ZeroDivisionError: division by zero
+"#,
+            file = file_path.display()
+        );
+        let mut out = HashMap::new();
+        parse_traceback(
+            &log,
+            "file:///tmp/dummy.py",
+            temp_dir.path().to_str().unwrap(),
+            &PathFilter::default(),
+            &mut out,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        assert_eq!(out[&uri][0]["data"]["fingerprint"], json!("return a / b"));
+    }
+
+    #[test]
+    fn skips_interceptor_frame_with_filter() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.c");
+        std::fs::write(&file_path, "").unwrap();
+        let root = temp_dir.path().to_str().unwrap();
+        let uri = format!("file://{}", file_path.display());
+        let rules = crate::config::PathsConfig {
+            exclude: vec!["vendor/**".to_string()],
+            ..Default::default()
+        };
+        let filter = PathFilter::new(&rules, &[root.to_string()]).unwrap();
+
+        let log = format!(
+            "==1==ERROR: AddressSanitizer: attempting double-free on 0x7b8f5e9e0010 in thread T0:
+    #0 0x5602fa52ec1d in free /usr/src/llvm/compiler-rt/lib/asan/asan_malloc_linux.cpp:52:3
+    #1 0x5602fa581047 in main {file}:12:3
+",
+            file = file_path.display()
+        );
+        let mut out = HashMap::new();
+        parse_san_error(&log, "file:///tmp/run.log", root, &filter, &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[&uri][0]["range"]["start"]["line"], json!(11));
+
+        // a crash in user code is reported where it happened
+        let log = format!(
+            "==1==ERROR: AddressSanitizer: SEGV on unknown address 0x000000000000
+    #0 0x5602fa581047 in deref {file}:5:7
+    #1 0x5602fa581047 in main {file}:12:3
+",
+            file = file_path.display()
+        );
+        let mut out = HashMap::new();
+        parse_san_error(&log, "file:///tmp/run.log", root, &filter, &mut out);
+        assert_eq!(out[&uri].len(), 1);
+        assert_eq!(out[&uri][0]["range"]["start"]["line"], json!(4));
+    }
+
+    #[test]
+    fn labels_oneline_origin() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.c");
+        std::fs::write(&file_path, "int main(void) { return 0; }").unwrap();
+
+        let log = "test.c:3:5: warning: unused variable 'x' [-Wunused-variable]\n\
+                   test.c:7:12: runtime error: signed integer overflow\n";
+        let mut out = HashMap::new();
+        parse_oneline(
+            log,
+            "file:///tmp/dummy.c",
+            temp_dir.path().to_str().unwrap(),
+            &mut out,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = out.get(&uri).expect("diagnostic missing for test.c");
+        assert_eq!(diags[0]["source"], json!("compiler"));
+        assert_eq!(diags[1]["source"], json!("ubsan"));
+    }
+
+    #[test]
+    fn parses_msbuild_diagnostics() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("Program.cs");
+        std::fs::write(&file_path, "// c# test file").unwrap();
+
+        let log = "Program.cs(12,5): error CS1002: ; expected [/work/app/app.csproj]\n\
+                   1>Program.cs(3): warning C4244: conversion from 'double' to 'int'\n\
+                   Program.cs(7,2,8,4): error: spans two lines\n";
+
+        let mut out = HashMap::new();
+        parse_msbuild(
+            log,
+            "file:///tmp/dummy.cs",
+            temp_dir.path().to_str().unwrap(),
+            &mut out,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = out.get(&uri).expect("diagnostic missing for msbuild file");
+        assert_eq!(diags.len(), 3);
+
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(11));
+        assert_eq!(diags[0]["range"]["start"]["character"], json!(4));
+        assert_eq!(diags[0]["code"], json!("CS1002"));
+        assert_eq!(diags[0]["message"], json!("; expected"));
+
+        assert_eq!(diags[1]["severity"], json!(2));
+        assert_eq!(diags[1]["code"], json!("C4244"));
+        assert_eq!(diags[1]["range"]["start"]["character"], json!(0));
+
+        assert_eq!(diags[2]["range"]["end"]["line"], json!(7));
+        assert_eq!(diags[2]["range"]["end"]["character"], json!(4));
+        assert!(diags[2].get("code").is_none());
+    }
+
+    #[test]
+    fn parses_location_header_blocks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let f90 = temp_dir.path().join("test.f90");
+        let hs = temp_dir.path().join("Main.hs");
+        std::fs::write(&f90, "! fortran test file").unwrap();
+        std::fs::write(&hs, "-- haskell test file").unwrap();
+
+        let log = format!(
+            "{f90}:3:10:\n\
+             \n\
+             \x20   3 |   x = y +\n\
+             \x20     |          1\n\
+             Error: Syntax error in expression at (1)\n\
+             {hs}:3:5-7: error: [GHC-88464]\n\
+             \x20   Variable not in scope: foo :: IO ()\n\
+             \x20 |\n\
+             3 |     foo\n\
+             \x20 |     ^^^\n\
+             {hs}:7:8: warning: [GHC-40910] [-Wunused-matches]\n\
+             \x20   Defined but not used: `x'\n",
+            f90 = f90.display(),
+            hs = hs.display()
+        );
+
+        let out = parse_diagnostics(
+            &log,
+            "file:///tmp/dummy",
+            temp_dir.path().to_str().unwrap(),
+            &[],
+        );
+
+        let f90_uri = format!("file://{}", f90.canonicalize().unwrap().display());
+        let diags = out
+            .get(&f90_uri)
+            .expect("diagnostic missing for fortran file");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(2));
+        assert_eq!(diags[0]["range"]["start"]["character"], json!(9));
+        assert_eq!(
+            diags[0]["message"],
+            json!("Syntax error in expression at (1)")
+        );
+
+        let hs_uri = format!("file://{}", hs.canonicalize().unwrap().display());
+        let diags = out
+            .get(&hs_uri)
+            .expect("diagnostic missing for haskell file");
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["range"]["end"]["character"], json!(7));
+        assert_eq!(diags[0]["code"], json!("GHC-88464"));
+        assert_eq!(
+            diags[0]["message"],
+            json!("Variable not in scope: foo :: IO ()")
+        );
+        assert_eq!(diags[1]["severity"], json!(2));
+        assert_eq!(diags[1]["code"], json!("GHC-40910"));
+        assert_eq!(
+            diags[1]["message"],
+            json!("Defined but not used: `x' [-Wunused-matches]")
+        );
+    }
+
+    #[test]
+    fn parses_gtest_and_catch2_failures() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.cpp");
+        std::fs::write(&file_path, "// c++ test file").unwrap();
+        let f = file_path.display();
+
+        let log = format!(
+            "[ RUN      ] FooTest.Bar\n\
+             {f}:12: Failure\n\
+             Expected equality of these values:\n\
+             \x20 x\n\
+             \x20   Which is: 1\n\
+             \x20 2\n\
+             [  FAILED  ] FooTest.Bar (0 ms)\n\
+             -------------------------------------------------------------------------------\n\
+             Factorials are computed\n\
+             -------------------------------------------------------------------------------\n\
+             {f}:15\n\
+             ...............................................................................\n\
+             \n\
+             {f}:20: FAILED:\n\
+             \x20 REQUIRE( Factorial(0) == 1 )\n\
+             with expansion:\n\
+             \x20 0 == 1\n\
+             \n\
+             ===============================================================================\n"
+        );
+
+        let out = parse_diagnostics(
+            &log,
+            "file:///tmp/dummy.cpp",
+            temp_dir.path().to_str().unwrap(),
+            &[],
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = out.get(&uri).expect("diagnostic missing for test file");
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["source"], json!("gtest"));
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(11));
+        assert_eq!(
+            diags[0]["message"],
+            json!("FooTest.Bar: Expected equality of these values:\n  x\n    Which is: 1\n  2")
+        );
+        assert_eq!(diags[1]["source"], json!("catch2"));
+        assert_eq!(diags[1]["range"]["start"]["line"], json!(19));
+        assert_eq!(
+            diags[1]["message"],
+            json!("Factorials are computed: REQUIRE( Factorial(0) == 1 )\nwith expansion:\n0 == 1")
+        );
+    }
 }
@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+const HEAD_LEN: usize = 64;
+
+// what changed in a log since the last read
+pub enum Tail {
+    Unchanged,
+    // complete lines written after the previous read
+    Appended(String),
+    // the log was truncated or replaced, this is its whole content
+    Reset(String),
+}
+
+// follows a log file by offset, so only the appended bytes are read
+pub struct LogTail {
+    path: PathBuf,
+    offset: u64,
+    // (dev, inode) of the file the offset belongs to
+    identity: Option<(u64, u64)>,
+    // first bytes of the file, a rewrite in place which grew past the offset changes them
+    head: Vec<u8>,
+    // bytes after the last newline, parsed once the line is complete
+    partial: Vec<u8>,
+    // text after the last point where no record was open, parsed again with the next chunk
+    pub carry: String,
+}
+
+impl LogTail {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        LogTail {
+            path: path.into(),
+            offset: 0,
+            identity: None,
+            head: Vec::new(),
+            partial: Vec::new(),
+            carry: String::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.offset = 0;
+        self.identity = None;
+        self.head.clear();
+        self.partial.clear();
+        self.carry.clear();
+    }
+
+    fn same_head(&self, file: &mut File) -> io::Result<bool> {
+        let mut head = vec![0u8; self.head.len()];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut head)?;
+        Ok(head == self.head)
+    }
+
+    pub fn read(&mut self) -> io::Result<Tail> {
+        let mut file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let was_read = self.identity.is_some();
+                self.reset();
+                return Ok(if was_read {
+                    Tail::Reset(String::new())
+                } else {
+                    Tail::Unchanged
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        let meta = file.metadata()?;
+        let identity = Some((meta.dev(), meta.ino()));
+        let reset =
+            identity != self.identity || meta.len() < self.offset || !self.same_head(&mut file)?;
+        if reset {
+            self.reset();
+            self.identity = identity;
+        } else if meta.len() == self.offset {
+            return Ok(Tail::Unchanged);
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = std::mem::take(&mut self.partial);
+        let n = file.read_to_end(&mut buf)?;
+        if self.offset == 0 {
+            self.head = buf[..buf.len().min(HEAD_LEN)].to_vec();
+        }
+        self.offset += n as u64;
+
+        let complete = match buf.iter().rposition(|b| *b == b'\n') {
+            Some(i) => {
+                self.partial = buf.split_off(i + 1);
+                String::from_utf8_lossy(&buf).into_owned()
+            }
+            None => {
+                self.partial = buf;
+                String::new()
+            }
+        };
+        Ok(if reset {
+            Tail::Reset(complete)
+        } else if complete.is_empty() {
+            Tail::Unchanged
+        } else {
+            Tail::Appended(complete)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn follows_appends_and_truncation() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("run.log");
+        std::fs::write(&path, "first\nsec").unwrap();
+
+        let mut tail = LogTail::new(&path);
+        assert!(matches!(tail.read().unwrap(), Tail::Reset(t) if t == "first\n"));
+        assert!(matches!(tail.read().unwrap(), Tail::Unchanged));
+
+        let mut f = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        f.write_all(b"ond\nthird\n").unwrap();
+        assert!(matches!(tail.read().unwrap(), Tail::Appended(t) if t == "second\nthird\n"));
+
+        std::fs::write(&path, "new\n").unwrap();
+        assert!(matches!(tail.read().unwrap(), Tail::Reset(t) if t == "new\n"));
+
+        std::fs::write(&path, "rewritten and longer than before\n").unwrap();
+        assert!(matches!(tail.read().unwrap(), Tail::Reset(t) if t.starts_with("rewritten")));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(tail.read().unwrap(), Tail::Reset(t) if t.is_empty()));
+        assert!(matches!(tail.read().unwrap(), Tail::Unchanged));
+    }
+}
@@ -1,10 +1,25 @@
+use crate::BuildPolicy;
+use crate::ClientWriter;
 use crate::IS_VERBOSE;
+use crate::Inputs;
+use crate::SharedClients;
 use crate::SharedStore;
+use crate::config::UserParser;
+use crate::coverage;
+use crate::fingerprint;
+use crate::ingest;
 use crate::log_parser;
+use crate::log_tail::{LogTail, Tail};
+use crate::lsp_io;
+use crate::path_filter::PathFilter;
+use crate::profile;
+use crate::report_parser;
+use crate::source::{self, LogSource};
 use anyhow::Result;
 use serde_json::{Value, json};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
 pub async fn create_publish_message(store: SharedStore, uri: &str) -> Result<Value> {
     let st = store.lock().await;
     let merged = st.merged_for(uri);
@@ -29,43 +44,487 @@ pub async fn create_publish_message(store: SharedStore, uri: &str) -> Result<Val
         }
     }))
 }
-pub async fn update_logs_store(
-    store: SharedStore,
-    build_log: &str,
-    run_log: &str,
-) -> Result<Vec<String>> {
-    let txt = read_to_string(build_log).unwrap_or_default()
-        + &read_to_string(run_log).unwrap_or_default();
-    unsafe {
-        if 0 < IS_VERBOSE {
-            eprintln!("[clasangd] Reading logs, total size: {} bytes", txt.len());
+pub async fn publish_uris(store: SharedStore, clients: SharedClients, uris: &[String]) {
+    for uri in uris {
+        let writers = clients.lock().await.showing(uri);
+        if writers.is_empty() {
+            continue;
+        }
+        match create_publish_message(store.clone(), uri).await {
+            Ok(msg) => {
+                unsafe {
+                    if 0 < IS_VERBOSE {
+                        eprintln!("[clasangd] start to publish");
+                        if 1 < IS_VERBOSE {
+                            eprintln!(
+                                "[clasangd] JSON: {}",
+                                serde_json::to_string_pretty(&msg).unwrap_or_default()
+                            );
+                        }
+                    }
+                }
+                for writer in writers {
+                    let mut w = writer.lock().await;
+                    if let Err(e) = lsp_io::write_lsp_message(&mut *w, &msg).await {
+                        eprintln!("[clasangd] Failed to publish diagnostics: {:#}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("[clasangd] Failed to create publish message: {:#}", e);
+            }
         }
     }
-    let saved_uri = {
-        let st = store.lock().await;
-        st.saved_uri.clone()
+}
+// editors which support it ask for the code lenses again, ex. the coverage file was rewritten
+pub async fn refresh_code_lens(clients: SharedClients) {
+    let (id, writers) = clients.lock().await.refreshing_lenses();
+    let msg = json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "workspace/codeLens/refresh"
+    });
+    for writer in writers {
+        let mut w = writer.lock().await;
+        if let Err(e) = lsp_io::write_lsp_message(&mut *w, &msg).await {
+            eprintln!("[clasangd] codeLens/refresh: {:#}", e);
+        }
+    }
+}
+// to one client only, ex. the files an editor already has diagnostics for when it connects
+pub async fn publish_to(store: SharedStore, writer: &ClientWriter, uris: &[String]) {
+    for uri in uris {
+        match create_publish_message(store.clone(), uri).await {
+            Ok(msg) => {
+                let mut w = writer.lock().await;
+                if let Err(e) = lsp_io::write_lsp_message(&mut *w, &msg).await {
+                    eprintln!("[clasangd] Failed to publish diagnostics: {:#}", e);
+                }
+            }
+            Err(e) => eprintln!("[clasangd] Failed to create publish message: {:#}", e),
+        }
+    }
+}
+
+// a carry larger than this is committed even if a report may still be open,
+// otherwise a program printing no report header would be parsed again and again
+const MAX_CARRY: usize = 64 * 1024;
+
+fn changed_under(changed: &HashSet<PathBuf>, path: &str) -> bool {
+    let path = source::absolute(Path::new(path));
+    changed.iter().any(|p| p.starts_with(&path))
+}
+
+type Diags = HashMap<String, Vec<Value>>;
+
+// splits text read from a log or a stream at the last report which may still be open.
+// returns the diagnostics before it, those of the open report, and the text to parse again
+// with the next chunk
+pub fn parse_chunk(
+    segment: &str,
+    saved_uri: &str,
+    root_uri: &str,
+    user_parsers: &[UserParser],
+    filter: &PathFilter,
+    source: &LogSource,
+) -> (Diags, Diags, String) {
+    let mut split = log_parser::last_record_start(segment, user_parsers);
+    if segment.len() - split > MAX_CARRY {
+        split = segment.len();
+    }
+    let (done, open) = segment.split_at(split);
+    let parse = |text: &str| {
+        let mut diags = log_parser::parse_selected(
+            text,
+            saved_uri,
+            root_uri,
+            user_parsers,
+            filter,
+            &source.parsers,
+        );
+        if let Some(label) = &source.label {
+            for d in diags.values_mut().flatten() {
+                d["source"] = json!(label);
+            }
+        }
+        fingerprint::relocate(&mut diags);
+        diags
     };
-    let root_uri = {
+    (parse(done), parse(open), open.to_string())
+}
+
+// reads what was appended to the logs and re-reads the other inputs which changed.
+// `full` starts over from the beginning of every input, e.g. after the parsers changed
+pub async fn update_logs_store(
+    store: SharedStore,
+    inputs: &Inputs,
+    tails: &mut HashMap<String, LogTail>,
+    changed: &HashSet<PathBuf>,
+    full: bool,
+) -> Result<Vec<String>> {
+    let (saved_uri, root_uri, user_parsers, path_filter) = {
         let st = store.lock().await;
-        st.root_path.clone()
+        (
+            st.saved_uri.clone(),
+            st.root_path.clone(),
+            st.user_parsers.clone(),
+            st.path_filter.clone(),
+        )
     };
+    let mut pub_uris: HashSet<String> = HashSet::new();
 
-    let logs_by_file = log_parser::parse_diagnostics(&txt, &saved_uri, &root_uri);
+    if full {
+        for name in inputs.sources.iter().flat_map(|s| &s.parsers) {
+            if !log_parser::BUILTIN_PARSERS.contains(&name.as_str())
+                && !user_parsers.iter().any(|p| p.name == *name)
+            {
+                eprintln!("[clasangd] unknown parser {} in --source", name);
+            }
+        }
+    }
 
-    unsafe {
-        if 0 < IS_VERBOSE {
-            eprintln!("[clasangd] Parsed logs for {} files", logs_by_file.len());
+    // a file matched by several sources is read by the first one
+    let mut logs: Vec<(String, &LogSource)> = Vec::new();
+    for source in &inputs.sources {
+        for path in source.expand() {
+            let path = path.to_string_lossy().into_owned();
+            if !logs.iter().any(|(p, _)| *p == path) {
+                logs.push((path, source));
+            }
         }
     }
-    for (uri, diags) in &logs_by_file {
-        eprintln!("[clasangd]   {}: {} diagnostics", uri, diags.len());
+    // files which no longer match take their diagnostics with them
+    let gone: Vec<String> = tails
+        .keys()
+        .filter(|k| !logs.iter().any(|(p, _)| p == *k))
+        .cloned()
+        .collect();
+    for log in gone {
+        tails.remove(&log);
+        let mut st = store.lock().await;
+        pub_uris.extend(st.update_input(&log, HashMap::new(), HashMap::new(), true));
+        pub_uris.extend(st.drop_restored(&log));
     }
 
-    let mut st = store.lock().await;
-    let old_uris: HashSet<String> = st.logs.keys().cloned().collect(); //古いやつ消すためにカラパブリッシュする
-    st.set_logs(logs_by_file);
-    let new_uris: HashSet<String> = st.logs.keys().cloned().collect();
-    let mut pub_uris: Vec<String> = new_uris.union(&old_uris).into_iter().cloned().collect();
+    for (log, source) in &logs {
+        let tail = tails
+            .entry(log.to_string())
+            .or_insert_with(|| LogTail::new(log));
+        if full {
+            tail.reset();
+        }
+        let (mut segment, mut reset) = match tail.read() {
+            Ok(Tail::Unchanged) => continue,
+            Ok(Tail::Appended(txt)) => (std::mem::take(&mut tail.carry) + &txt, false),
+            Ok(Tail::Reset(txt)) => (txt, true),
+            Err(e) => {
+                eprintln!("[clasangd] failed to read {}: {:#}", log, e);
+                continue;
+            }
+        };
+        // `clasangd exec` starts every run with a marker, older runs are dropped with it
+        // so that a carry without a report doesn't start the run again
+        if let Some(start) = ingest::last_run_start(&segment) {
+            segment.drain(..start);
+            reset = true;
+        }
+        unsafe {
+            if 0 < IS_VERBOSE {
+                eprintln!(
+                    "[clasangd] Reading {}, {} bytes{}",
+                    log,
+                    segment.len(),
+                    if reset { " from the start" } else { "" }
+                );
+            }
+        }
+        let (committed, provisional, carry) = parse_chunk(
+            &segment,
+            &saved_uri,
+            &root_uri,
+            &user_parsers,
+            &path_filter,
+            source,
+        );
+        tail.carry = carry;
+
+        // an empty log at startup keeps what was restored from the last session
+        let read_again = !full || !committed.is_empty() || !provisional.is_empty();
+        let mut st = store.lock().await;
+        pub_uris.extend(st.update_input(log, committed, provisional, reset));
+        if read_again {
+            pub_uris.extend(st.drop_restored(log));
+        }
+
+        if reset && !full && *log == inputs.sources[0].pattern {
+            let cleared: Vec<String> = st
+                .inputs
+                .keys()
+                .filter(|k| match inputs.on_build {
+                    BuildPolicy::Keep => false,
+                    BuildPolicy::ClearRun => **k == inputs.sources[1].pattern,
+                    BuildPolicy::ClearAll => {
+                        *k != log && (k.starts_with("stream:") || logs.iter().any(|(p, _)| p == *k))
+                    }
+                })
+                .cloned()
+                .collect();
+            for input in cleared {
+                if let Some(t) = tails.get_mut(&input) {
+                    t.carry.clear();
+                }
+                pub_uris.extend(st.update_input(&input, HashMap::new(), HashMap::new(), true));
+            }
+        }
+    }
+
+    // the other inputs are whole documents, they are parsed again when they change
+    if full || inputs.reports.iter().any(|r| changed_under(changed, r)) {
+        let mut diags = HashMap::new();
+        report_parser::parse_reports(
+            &inputs.reports,
+            &saved_uri,
+            &root_uri,
+            &user_parsers,
+            &mut diags,
+        );
+        let mut st = store.lock().await;
+        pub_uris.extend(st.update_input("reports", diags, HashMap::new(), true));
+        pub_uris.extend(st.drop_restored("reports"));
+    }
+    if full || inputs.coverage.iter().any(|c| changed_under(changed, c)) {
+        let mut diags = HashMap::new();
+        let mut lenses = HashMap::new();
+        for cov in &inputs.coverage {
+            if let Ok(lcov) = read_to_string(cov) {
+                coverage::parse_lcov(&lcov, &saved_uri, &root_uri, &mut diags, &mut lenses);
+            }
+        }
+        let mut st = store.lock().await;
+        pub_uris.extend(st.update_input("coverage", diags, HashMap::new(), true));
+        pub_uris.extend(st.drop_restored("coverage"));
+        st.lens_changed |= st.coverage_lens && st.code_lens != lenses;
+        st.code_lens = lenses;
+    }
+    for prof in &inputs.profiles {
+        if !full && !changed_under(changed, prof) {
+            continue;
+        }
+        let mut diags = HashMap::new();
+        if let Ok(report) = read_to_string(prof) {
+            profile::parse_profile(
+                &report,
+                &saved_uri,
+                &root_uri,
+                inputs.profile_top,
+                &mut diags,
+            );
+        }
+        let mut st = store.lock().await;
+        pub_uris.extend(st.update_input(prof, diags, HashMap::new(), true));
+        pub_uris.extend(st.drop_restored(prof));
+    }
+
+    let st = store.lock().await;
+    unsafe {
+        if 0 < IS_VERBOSE {
+            eprintln!("[clasangd] Updated diagnostics of {} files", pub_uris.len());
+            for uri in &pub_uris {
+                eprintln!(
+                    "[clasangd]   {}: {} diagnostics",
+                    uri,
+                    st.logs.get(uri).map(|v| v.len()).unwrap_or(0)
+                );
+            }
+        }
+    }
+    // a uri whose diagnostics are gone is published empty
+    let mut pub_uris: Vec<String> = pub_uris.into_iter().collect();
     pub_uris.sort();
     Ok(pub_uris)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientWriter, Clients, DiagStore};
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    #[tokio::test]
+    async fn build_policy_clears_older_runs() {
+        for (policy, left) in [
+            (BuildPolicy::Keep, vec!["built again", "crashed", "served"]),
+            (BuildPolicy::ClearRun, vec!["built again", "served"]),
+            (BuildPolicy::ClearAll, vec!["built again"]),
+        ] {
+            let temp_dir = tempfile::tempdir().unwrap();
+            let dir = temp_dir.path();
+            std::fs::write(dir.join("a.c"), "int main(void) { return 0; }").unwrap();
+            let root = dir.to_str().unwrap().to_string();
+            let log = |name: &str| dir.join(name).to_str().unwrap().to_string();
+            std::fs::write(log("build.log"), "a.c:1:1: warning: built\n").unwrap();
+            std::fs::write(log("run.log"), "a.c:2:1: runtime error: crashed\n").unwrap();
+            std::fs::write(log("server.log"), "a.c:3:1: error: served\n").unwrap();
+            let inputs = Inputs {
+                sources: ["build.log", "run.log", "server.log"]
+                    .map(|name| LogSource::file(&log(name)))
+                    .into(),
+                on_build: policy,
+                ..Default::default()
+            };
+            let store: SharedStore = Arc::new(Mutex::new(DiagStore {
+                root_path: root.clone(),
+                ..Default::default()
+            }));
+            let mut tails = HashMap::new();
+            update_logs_store(store.clone(), &inputs, &mut tails, &HashSet::new(), false)
+                .await
+                .unwrap();
+
+            // the next build starts
+            let mut build = std::fs::OpenOptions::new()
+                .append(true)
+                .open(log("build.log"))
+                .unwrap();
+            std::io::Write::write_all(
+                &mut build,
+                format!(
+                    "{}run-start\na.c:1:1: warning: built again\n",
+                    ingest::MARKER
+                )
+                .as_bytes(),
+            )
+            .unwrap();
+            update_logs_store(store.clone(), &inputs, &mut tails, &HashSet::new(), false)
+                .await
+                .unwrap();
+
+            let st = store.lock().await;
+            let mut messages: Vec<&str> = st
+                .logs
+                .values()
+                .flatten()
+                .filter_map(|d| d["message"].as_str())
+                .collect();
+            messages.sort();
+            assert_eq!(messages, left, "{:?}", policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn run_start_resets_once() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("a.c"), "int main(void) { return 0; }").unwrap();
+        let log = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let append = |name: &str, txt: &str| {
+            let mut f = std::fs::OpenOptions::new()
+                .append(true)
+                .open(log(name))
+                .unwrap();
+            std::io::Write::write_all(&mut f, txt.as_bytes()).unwrap();
+        };
+        std::fs::write(log("build.log"), "").unwrap();
+        std::fs::write(log("run.log"), "").unwrap();
+        let inputs = Inputs {
+            sources: ["build.log", "run.log"]
+                .map(|name| LogSource::file(&log(name)))
+                .into(),
+            on_build: BuildPolicy::ClearRun,
+            ..Default::default()
+        };
+        let store: SharedStore = Arc::new(Mutex::new(DiagStore {
+            root_path: dir.to_str().unwrap().to_string(),
+            ..Default::default()
+        }));
+        let mut tails = HashMap::new();
+        let mut update = async || {
+            update_logs_store(store.clone(), &inputs, &mut tails, &HashSet::new(), false)
+                .await
+                .unwrap();
+        };
+        update().await;
+
+        // a build which has printed no report yet, then the program runs
+        append(
+            "build.log",
+            &format!("{}run-start\nbuilding\n", ingest::MARKER),
+        );
+        update().await;
+        append("run.log", "a.c:2:1: runtime error: crashed\n");
+        update().await;
+        append("build.log", "linking\n");
+        update().await;
+
+        let st = store.lock().await;
+        let messages: Vec<&str> = st
+            .logs
+            .values()
+            .flatten()
+            .filter_map(|d| d["message"].as_str())
+            .collect();
+        assert_eq!(messages, ["crashed"]);
+    }
+
+    #[tokio::test]
+    async fn asks_for_lenses_again_when_the_coverage_changes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        std::fs::write(dir.join("a.c"), "int main(void) { return 0; }").unwrap();
+        let cov = dir.join("cov.info");
+        let lcov = |hits: u64| {
+            format!(
+                "SF:{}\nFN:1,main\nFNDA:{},main\nend_of_record\n",
+                dir.join("a.c").display(),
+                hits
+            )
+        };
+        std::fs::write(&cov, lcov(1)).unwrap();
+        let inputs = Inputs {
+            coverage: vec![cov.to_str().unwrap().to_string()],
+            ..Default::default()
+        };
+        let store: SharedStore = Arc::new(Mutex::new(DiagStore {
+            root_path: dir.to_str().unwrap().to_string(),
+            coverage_lens: true,
+            ..Default::default()
+        }));
+        let mut tails = HashMap::new();
+        let changed = HashSet::from([cov.clone()]);
+        update_logs_store(store.clone(), &inputs, &mut tails, &changed, true)
+            .await
+            .unwrap();
+        assert!(std::mem::take(&mut store.lock().await.lens_changed));
+        update_logs_store(store.clone(), &inputs, &mut tails, &changed, false)
+            .await
+            .unwrap();
+        assert!(!store.lock().await.lens_changed);
+        std::fs::write(&cov, lcov(2)).unwrap();
+        update_logs_store(store.clone(), &inputs, &mut tails, &changed, false)
+            .await
+            .unwrap();
+        assert!(store.lock().await.lens_changed);
+
+        // only the editor which supports the request gets it
+        let mut clients = Clients::default();
+        let mut readers = Vec::new();
+        for refresh in [true, false] {
+            let (reader, writer) = tokio::io::duplex(1 << 12);
+            let writer: ClientWriter = Arc::new(Mutex::new(Box::new(writer)));
+            let id = clients.add(writer, None);
+            clients.set_lens_refresh(id, refresh);
+            readers.push(reader);
+        }
+        let clients: SharedClients = Arc::new(Mutex::new(clients));
+        refresh_code_lens(clients.clone()).await;
+        let msg = lsp_io::read_lsp_message(&mut readers[0]).await.unwrap();
+        assert_eq!(msg["method"], json!("workspace/codeLens/refresh"));
+        let other = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            lsp_io::read_lsp_message(&mut readers[1]),
+        )
+        .await;
+        assert!(other.is_err());
+    }
+}
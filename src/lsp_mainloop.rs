@@ -1,18 +1,32 @@
+use crate::ClientWriter;
 use crate::IS_VERBOSE;
-use crate::SharedClientWriter;
+use crate::Inputs;
+use crate::SharedClients;
 use crate::SharedStore;
+use crate::cache;
+use crate::config;
+use crate::edit::LineEdit;
+use crate::file_index;
+use crate::log_tail::LogTail;
 use crate::lsp_diagnosis;
 use crate::lsp_io;
+use crate::remap;
+use crate::source;
 use anyhow::Result;
 use notify::event::{EventKind, ModifyKind};
 use notify::{Event, RecursiveMode, Watcher};
 use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::io::ErrorKind;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use tokio::io::AsyncRead;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 pub async fn client_to_server_loop<R>(
     mut client_reader: R,
-    client_writer: SharedClientWriter,
+    client_writer: ClientWriter,
+    client_id: u64,
+    clients: SharedClients,
+    config_tx: UnboundedSender<Vec<PathBuf>>,
     store: SharedStore,
 ) where
     R: AsyncRead + Unpin,
@@ -28,7 +42,10 @@ pub async fn client_to_server_loop<R>(
         };
         unsafe {
             if 0 < IS_VERBOSE {
-                eprintln!("[clasangd] receive: {}", msg.get("method").unwrap());
+                eprintln!(
+                    "[clasangd] receive: {}",
+                    msg.get("method").unwrap_or(&json!("response"))
+                );
                 if 1 < IS_VERBOSE {
                     eprintln!(
                         "[clasangd] json: {}",
@@ -38,27 +55,64 @@ pub async fn client_to_server_loop<R>(
             }
         }
 
-        if let Some(method) = msg.get("method").and_then(|m| m.as_str()) {
-            if method == "textDocument/didOpen"
+        if msg.get("method").and_then(|m| m.as_str()) == Some("textDocument/didClose")
+            && let Some(uri) = msg
+                .get("params")
+                .and_then(|p| p.get("textDocument"))
+                .and_then(|t| t.get("uri"))
+                .and_then(|u| u.as_str())
+        {
+            clients.lock().await.set_open(client_id, uri, false);
+        }
+        if let Some(method) = msg.get("method").and_then(|m| m.as_str())
+            && (method == "textDocument/didOpen"
                 || method == "textDocument/didChange"
-                || method == "textDocument/didSave"
-            {
-                let params = msg.get("params").cloned().unwrap_or_else(|| json!({}));
-                let uri = params
-                    .get("textDocument")
-                    .and_then(|u| u.get("uri"))
-                    .and_then(|u| u.as_str())
-                    .unwrap_or_default()
-                    .to_string();
+                || method == "textDocument/didSave")
+        {
+            let params = msg.get("params").cloned().unwrap_or_else(|| json!({}));
+            let uri = params
+                .get("textDocument")
+                .and_then(|u| u.get("uri"))
+                .and_then(|u| u.as_str())
+                .unwrap_or_default()
+                .to_string();
 
-                if !uri.is_empty() {
-                    unsafe {
-                        if 0 < IS_VERBOSE {
-                            eprintln!("[clasangd] Setting saved_uri to: {}", uri);
-                        }
+            if !uri.is_empty() {
+                unsafe {
+                    if 0 < IS_VERBOSE {
+                        eprintln!("[clasangd] Setting saved_uri to: {}", uri);
+                    }
+                }
+                if method == "textDocument/didOpen" {
+                    clients.lock().await.set_open(client_id, &uri, true);
+                    // the diagnostics found before this editor opened the file
+                    let known = store.lock().await.logs.contains_key(&uri);
+                    if known {
+                        lsp_diagnosis::publish_to(
+                            store.clone(),
+                            &client_writer,
+                            std::slice::from_ref(&uri),
+                        )
+                        .await;
+                    }
+                }
+                let mut st = store.lock().await;
+                st.saved_uri = uri.clone();
+                if method == "textDocument/didChange" {
+                    let edits: Vec<Option<LineEdit>> = params
+                        .get("contentChanges")
+                        .and_then(|c| c.as_array())
+                        .into_iter()
+                        .flatten()
+                        .map(LineEdit::from_change)
+                        .collect();
+                    if st.logs.contains_key(&uri) || st.code_lens.contains_key(&uri) {
+                        let mut uris: Vec<String> =
+                            st.apply_edits(&uri, &edits).into_iter().collect();
+                        drop(st);
+                        uris.sort();
+                        lsp_diagnosis::publish_uris(store.clone(), clients.clone(), &uris).await;
                     }
-                    let mut st = store.lock().await;
-                    st.saved_uri = uri;
                 }
             }
         }
@@ -70,15 +124,78 @@ pub async fn client_to_server_loop<R>(
                 .and_then(|u| u.as_str())
                 .unwrap_or_default()
                 .to_string();
+            // ex. "workspaceFolders": [{"uri": "file:///home/user/lib", "name": "lib"}]
+            let folders: Vec<String> = msg
+                .get("params")
+                .and_then(|p| p.get("workspaceFolders"))
+                .and_then(|f| f.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|f| f.get("uri")?.as_str()?.strip_prefix("file://"))
+                .map(str::to_string)
+                .collect();
+            let lens_refresh = msg
+                .pointer("/params/capabilities/workspace/codeLens/refreshSupport")
+                .and_then(|r| r.as_bool())
+                .unwrap_or(false);
+            clients
+                .lock()
+                .await
+                .set_lens_refresh(client_id, lens_refresh);
+            // another editor of the same workspace attached to the daemon
+            let (reload, user_config) = {
+                let st = store.lock().await;
+                (
+                    st.root_path != path || st.config_paths.is_empty(),
+                    st.user_config.clone(),
+                )
+            };
+            // the config and the compilation database are read without holding the store
+            let loaded = reload.then(|| {
+                let config_paths = config::config_paths(user_config.as_deref(), &path);
+                let roots: Vec<String> = std::iter::once(path.clone()).chain(folders).collect();
+                let configs = config::load_configs(&config_paths);
+                let user_parsers = config::user_parsers(&configs);
+                let path_filter = config::path_filter(&configs, &roots);
+                let remaps = config::remaps(&configs, &path);
+                (config_paths, configs, roots, user_parsers, path_filter, remaps)
+            });
             let mut st = store.lock().await;
-            st.root_path = path;
+            if let Some((config_paths, configs, roots, user_parsers, path_filter, remaps)) = loaded {
+                st.config_paths = config_paths;
+                st.configs = configs;
+                st.user_parsers = user_parsers;
+                st.roots = roots;
+                st.path_filter = path_filter;
+                remap::set_rules(&path, remaps);
+                // clients find the logs by the directory the server was started in
+                if let Some(dir) = &st.named_after
+                    && Path::new(&path).canonicalize().is_ok_and(|p| p != *dir)
+                {
+                    eprintln!(
+                        "[clasangd] logs are named after {}, not the workspace {}, start the server in it or pass --workspace",
+                        dir.display(),
+                        path
+                    );
+                }
+                st.root_path = path;
+                let _ = config_tx.send(st.config_paths.clone());
+            }
+            let mut capabilities = json!({
+                "textDocumentSync": 2
+            });
+            if st.coverage_lens {
+                capabilities["codeLensProvider"] = json!({ "resolveProvider": false });
+            }
+            capabilities["executeCommandProvider"] = json!({
+                "commands": ["clasangd.toggleSource"]
+            });
+            drop(st);
             let reply = json!({
                 "jsonrpc": "2.0",
                 "id": msg.get("id"),
                 "result": {
-                    "capabilities": {
-                        "textDocumentSync": 1
-                    }
+                    "capabilities": capabilities
                 }
             });
             let mut w = client_writer.lock().await;
@@ -86,7 +203,105 @@ pub async fn client_to_server_loop<R>(
                 eprintln!("[clasangd] Failed to publish diagnostics: {:#}", e);
             }
         }
+        if msg.get("method").and_then(|m| m.as_str()) == Some("initialized") {
+            let mut st = store.lock().await;
+            if st.cache_restored {
+                // a later session, the diagnostics are already known
+                let mut uris: Vec<String> = st.logs.keys().cloned().collect();
+                uris.sort();
+                drop(st);
+                let cl = clients.lock().await;
+                uris.retain(|uri| cl.shows(client_id, uri));
+                drop(cl);
+                lsp_diagnosis::publish_to(store.clone(), &client_writer, &uris).await;
+            } else {
+                st.cache_restored = true;
+                let saved = st
+                    .cache_dir
+                    .as_deref()
+                    .and_then(|d| cache::cache_path(d, &st.root_path));
+                if let Some(path) = saved.filter(|p| p.is_file()) {
+                    match cache::load(&path) {
+                        Ok(saved) => {
+                            let mut uris: Vec<String> = st.restore(saved).into_iter().collect();
+                            uris.sort();
+                            drop(st);
+                            unsafe {
+                                if 0 < IS_VERBOSE {
+                                    eprintln!(
+                                        "[clasangd] Restored diagnostics of {} files",
+                                        uris.len()
+                                    );
+                                }
+                            }
+                            lsp_diagnosis::publish_uris(store.clone(), clients.clone(), &uris)
+                                .await;
+                        }
+                        Err(e) => eprintln!("[clasangd] failed to restore diagnostics: {:#}", e),
+                    }
+                }
+            }
+        }
+        if msg.get("method").and_then(|m| m.as_str()) == Some("textDocument/codeLens") {
+            let uri = msg
+                .get("params")
+                .and_then(|p| p.get("textDocument"))
+                .and_then(|t| t.get("uri"))
+                .and_then(|u| u.as_str())
+                .unwrap_or_default();
+            let lenses = {
+                let st = store.lock().await;
+                st.code_lens.get(uri).cloned().unwrap_or_default()
+            };
+            let reply = json!({
+                "jsonrpc": "2.0",
+                "id": msg.get("id"),
+                "result": lenses
+            });
+            let mut w = client_writer.lock().await;
+            if let Err(e) = lsp_io::write_lsp_message(&mut *w, &reply).await {
+                eprintln!("[clasangd] codeLens: {:#}", e);
+            }
+        }
+        if msg.get("method").and_then(|m| m.as_str()) == Some("workspace/executeCommand") {
+            // ex. {"command": "clasangd.toggleSource", "arguments": ["profile"]}
+            let params = msg.get("params").cloned().unwrap_or_else(|| json!({}));
+            let source = params
+                .get("arguments")
+                .and_then(|a| a.get(0))
+                .and_then(|a| a.as_str())
+                .unwrap_or_default()
+                .to_string();
+            let result = if params.get("command").and_then(|c| c.as_str())
+                == Some("clasangd.toggleSource")
+                && !source.is_empty()
+            {
+                let mut st = store.lock().await;
+                let shown = !st.hidden_sources.remove(&source);
+                if shown {
+                    st.hidden_sources.insert(source.clone());
+                }
+                json!({ "source": source, "visible": !shown })
+            } else {
+                json!(null)
+            };
+            let reply = json!({
+                "jsonrpc": "2.0",
+                "id": msg.get("id"),
+                "result": result
+            });
+            {
+                let mut w = client_writer.lock().await;
+                if let Err(e) = lsp_io::write_lsp_message(&mut *w, &reply).await {
+                    eprintln!("[clasangd] executeCommand: {:#}", e);
+                }
+            }
+            let mut uris: Vec<String> = store.lock().await.logs.keys().cloned().collect();
+            uris.sort();
+            lsp_diagnosis::publish_uris(store.clone(), clients.clone(), &uris).await;
+        }
         if msg.get("method").and_then(|m| m.as_str()) == Some("shutdown") {
+            store.lock().await.save_cache();
             let reply = json!({
             "jsonrpc": "2.0",
             "id": msg.get("id"),
@@ -98,66 +313,219 @@ pub async fn client_to_server_loop<R>(
             }
         }
         if msg.get("method").and_then(|m| m.as_str()) == Some("exit") {
-            std::process::exit(0);
+            break;
         }
     }
 }
 pub async fn detect_change_publish(
-    build_log: String,
-    run_log: String,
-    client_writer: SharedClientWriter,
+    inputs: Inputs,
+    mut config_rx: UnboundedReceiver<Vec<PathBuf>>,
+    clients: SharedClients,
     store: SharedStore,
 ) -> Result<()> {
     let (tx, mut rx) = tokio::sync::mpsc::channel(100);
-    let mut watcher = notify::recommended_watcher(move |res: Result<Event, _>| {
-        if let Ok(event) = res {
+    let mut watcher = match notify::recommended_watcher(move |res: Result<Event, _>| {
+        if let Ok(event) = res
+            && !matches!(event.kind, EventKind::Access(_))
+        {
             let _ = tx.blocking_send(event);
         }
-    })
-    .unwrap();
-    watcher
-        .watch(Path::new(&build_log), RecursiveMode::NonRecursive)
-        .unwrap();
-    watcher
-        .watch(Path::new(&run_log), RecursiveMode::NonRecursive)
-        .unwrap();
-    let mut pending = false;
+    }) {
+        Ok(w) => w,
+        Err(e) => {
+            eprintln!("[clasangd] failed to create a file watcher: {:#}", e);
+            return Err(e.into());
+        }
+    };
+    let extra: Vec<&String> = inputs
+        .reports
+        .iter()
+        .chain(&inputs.coverage)
+        .chain(&inputs.profiles)
+        .collect();
+    // the watched directories, one which is removed is watched again once it is created
+    let mut watched: HashMap<PathBuf, RecursiveMode> = HashMap::new();
+    let mut retry = tokio::time::interval(tokio::time::Duration::from_secs(1));
+    // the reports and the coverage may have been written before the server started
+    let mut pending = !extra.is_empty();
+    let mut config_paths: Vec<PathBuf> = Vec::new();
+    // its directories are watched to keep the file index up to date
+    let mut root: Option<PathBuf> = None;
+    // the system's limit of watches was reached, the root's directories are left unwatched
+    let mut limit_reached = false;
+    // the root's directories were watched once, a directory found later may already have files
+    let mut tree_watched = false;
+    // the root's directories, collected again only when a directory is created or removed
+    let mut tree: HashSet<PathBuf> = HashSet::new();
+    let mut tree_changed = false;
+    let mut tails: HashMap<String, LogTail> = HashMap::new();
+    // paths touched since the last update, and whether everything must be read again
+    let mut changed: HashSet<PathBuf> = HashSet::new();
+    let mut full = true;
     loop {
         tokio::select! {
-             Some(event) = rx.recv() => {
-                 // Modifyイベントの時だけpendingをセット
-                 if matches!(event.kind, EventKind::Modify(ModifyKind::Data(_))) {
-                     pending = true;
+             _ = retry.tick() => {
+                 // logs and config files may be created later, so their directories are watched
+                 let targets: HashMap<PathBuf, RecursiveMode> = inputs
+                     .sources
+                     .iter()
+                     .map(|s| s.watch_target())
+                     .chain(extra.iter().map(|p| (source::watch_dir(Path::new(p)), RecursiveMode::NonRecursive)))
+                     .chain(config_paths.iter().map(|p| (source::watch_dir(p), RecursiveMode::NonRecursive)))
+                     .collect();
+                 // one by one after the logs, target/, node_modules/, .git/ and the like are not watched
+                 let rescan = std::mem::take(&mut tree_changed);
+                 if rescan {
+                     tree = match root.as_ref().filter(|_| !limit_reached) {
+                         Some(r) => file_index::dirs(r)
+                             .into_iter()
+                             .filter(|d| !targets.contains_key(d))
+                             .collect(),
+                         None => HashSet::new(),
+                     };
                  }
-             }
-             _ = tokio::time::sleep(tokio::time::Duration::from_millis(300)), if pending => {
-                 pending = false;
-
-                 if let Ok(uris) = lsp_diagnosis::update_logs_store(store.clone(), &build_log, &run_log).await {
-                     for uri in uris{
-                     match lsp_diagnosis::create_publish_message(store.clone(),&uri).await {
-                         Ok(msg) => {
+                 // gone, or of a previous root
+                 watched.retain(|dir, _| {
+                     let keep = tree.contains(dir)
+                         || (dir.is_dir() && targets.contains_key(dir))
+                         || limit_reached;
+                     if !keep {
+                         let _ = watcher.unwatch(dir);
+                     }
+                     keep
+                 });
+                 // already watched ones are skipped below, so only after a rescan
+                 let new_tree: Vec<PathBuf> = if rescan {
+                     tree.iter().cloned().collect()
+                 } else {
+                     Vec::new()
+                 };
+                 let new_tree = new_tree.into_iter().map(|d| (d, RecursiveMode::NonRecursive));
+                 for (dir, mode) in targets.into_iter().chain(new_tree) {
+                     if watched.get(&dir) == Some(&mode) || !dir.is_dir() {
+                         continue;
+                     }
+                     // a log directory which turned out to be the root
+                     if watched.remove(&dir).is_some() {
+                         let _ = watcher.unwatch(&dir);
+                     }
+                     match watcher.watch(&dir, mode) {
+                         Ok(()) => {
                              unsafe {
                                  if 0 < IS_VERBOSE {
-                                     eprintln!("[clasangd] start to publish");
-                                     if 1 < IS_VERBOSE {
-                                         eprintln!(
-                                             "[clasangd] JSON: {}",
-                                             serde_json::to_string_pretty(&msg).unwrap_or_default()
-                                         );
-                                     }
+                                     eprintln!("[clasangd] Watching {}", dir.display());
                                  }
                              }
-                             let mut w = client_writer.lock().await;
-                             if let Err(e) = lsp_io::write_lsp_message(&mut *w, &msg).await {
-                                 eprintln!("[clasangd] Failed to publish diagnostics: {:#}", e);
+                             // files may have been written before the watch
+                             if tree_watched {
+                                 file_index::update(&dir);
                              }
+                             watched.insert(dir, mode);
+                             pending = true;
                          }
-                         Err(e) => {
-                             eprintln!("[clasangd] Failed to create publish message: {:#}", e);
+                         Err(e) if matches!(e.kind, notify::ErrorKind::MaxFilesWatch) => {
+                             if !limit_reached {
+                                 eprintln!(
+                                     "[clasangd] failed to watch {}: {:#}, raise fs.inotify.max_user_watches to index new files",
+                                     dir.display(),
+                                     e
+                                 );
+                             }
+                             limit_reached = true;
+                             break;
+                         }
+                         Err(e) => eprintln!("[clasangd] failed to watch {}: {:#}", dir.display(), e),
+                     }
+                 }
+                 tree_watched = root.is_some();
+             }
+             Some(paths) = config_rx.recv() => {
+                 config_paths = paths;
+                 let root_path = store.lock().await.root_path.clone();
+                 root = (!root_path.is_empty()).then(|| PathBuf::from(root_path));
+                 limit_reached = false;
+                 tree_watched = false;
+                 tree_changed = true;
+                 retry.reset_immediately();
+                 // the root path changed, so did the uris
+                 full = true;
+                 pending = true;
+             }
+             Some(event) = rx.recv() => {
+                 if matches!(
+                     event.kind,
+                     EventKind::Create(_) | EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_))
+                 ) {
+                     for p in event.paths.iter().filter(|p| root.as_ref().is_some_and(|r| p.starts_with(r))) {
+                         file_index::update(p);
+                         tree_changed |= p.is_dir() || tree.contains(p);
+                     }
+                 }
+                 // a build wrote a new compilation database, its prefix maps may have changed
+                 if !matches!(event.kind, EventKind::Access(_))
+                     && let Some(r) = &root
+                     && event.paths.iter().any(|p| remap::COMPILE_COMMANDS.iter().any(|c| *p == r.join(c)))
+                 {
+                     let root_path = r.to_string_lossy();
+                     let configs = store.lock().await.configs.clone();
+                     if remap::set_rules(&root_path, config::remaps(&configs, &root_path)) {
+                         unsafe {
+                             if 0 < IS_VERBOSE {
+                                 eprintln!("[clasangd] Reloaded prefix maps of {}", root_path);
+                             }
                          }
+                         full = true;
+                         pending = true;
                      }
+                 }
+                 if !matches!(event.kind, EventKind::Access(_))
+                     && event.paths.iter().any(|p| config_paths.contains(p))
+                 {
+                     let configs = config::load_configs(&config_paths);
+                     let parsers = config::user_parsers(&configs);
+                     unsafe {
+                         if 0 < IS_VERBOSE {
+                             eprintln!("[clasangd] Reloaded config, {} user parsers", parsers.len());
+                         }
+                     }
+                     let (root_path, roots) = {
+                         let st = store.lock().await;
+                         (st.root_path.clone(), st.roots.clone())
+                     };
+                     let path_filter = config::path_filter(&configs, &roots);
+                     remap::set_rules(&root_path, config::remaps(&configs, &root_path));
+                     let mut st = store.lock().await;
+                     st.configs = configs;
+                     st.user_parsers = parsers;
+                     st.path_filter = path_filter;
+                     drop(st);
+                     full = true;
+                     pending = true;
+                 }
+                 // a log or report which is created, removed, renamed or written is read again
+                 if !matches!(event.kind, EventKind::Access(_))
+                     && event.paths.iter().any(|p| {
+                         inputs.sources.iter().any(|s| s.matches(p))
+                             || extra.iter().any(|e| p.starts_with(source::absolute(Path::new(e))))
+                     })
+                 {
+                     changed.extend(event.paths.iter().cloned());
+                     pending = true;
+                 }
+             }
+             _ = tokio::time::sleep(tokio::time::Duration::from_millis(300)), if pending => {
+                 pending = false;
 
+                 let update =
+                     lsp_diagnosis::update_logs_store(store.clone(), &inputs, &mut tails, &changed, full)
+                         .await;
+                 changed.clear();
+                 full = false;
+                 if let Ok(uris) = update {
+                     lsp_diagnosis::publish_uris(store.clone(), clients.clone(), &uris).await;
+                     let refresh = std::mem::take(&mut store.lock().await.lens_changed);
+                     if refresh {
+                         lsp_diagnosis::refresh_code_lens(clients.clone()).await;
                      }
                  } else {
                      eprintln!("[clasangd] Failed to update logs");
@@ -1,40 +1,389 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use serde_json::Value;
 use std::{
-    collections::{HashMap, HashSet},
-    fs::File,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
     sync::Arc,
 };
 use tokio::{
-    io::{self, BufReader, Stdin, Stdout},
+    io::{self, AsyncWrite},
     sync::Mutex,
 };
 
+mod aggregate;
+mod cache;
+mod config;
+mod coverage;
+mod daemon;
+mod edit;
+mod errorformat;
+mod exec;
+mod file_index;
+mod fingerprint;
+mod ingest;
 mod log_parser;
+mod log_tail;
 mod lsp_diagnosis;
 mod lsp_io;
 mod lsp_mainloop;
+mod path_filter;
+mod profile;
+mod remap;
+mod report_parser;
+mod runtime;
+mod source;
+mod symbolize;
+mod transport;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    #[arg(short, long,help="set log file [basename] to make both of [basename]_build.log and [basename]_run.log,or set preferred log location like /tmp/[basename]",default_value_t=("/tmp/clasangd").to_string())]
-    name: String,
+    #[arg(
+        short,
+        long,
+        help = "set log file [basename] to make both of [basename]_build.log and [basename]_run.log,or set preferred log location like /tmp/[basename] [default: a per-workspace path in $XDG_RUNTIME_DIR/clasangd, named after --workspace, not the editor's root]"
+    )]
+    name: Option<String>,
+    #[arg(
+        short,
+        long,
+        help = "workspace the default log location belongs to. the logs are open before the editor sends its root, so an editor which starts the server outside the root must pass it [default: the current directory, or the parent a server runs for]"
+    )]
+    workspace: Option<PathBuf>,
     #[arg(short, long, help = "set verbose level", default_value_t = 0)]
     verbose: u8,
+    #[arg(
+        long,
+        help = "parse the existing build and run logs at startup instead of truncating them"
+    )]
+    keep_logs: bool,
+    #[arg(
+        short,
+        long,
+        value_parser = source::LogSource::parse,
+        help = "watch more logs: [LABEL=]PATH[:PARSER,...], PATH is a file, a glob or a directory, an existing PATH is taken as a whole (repeatable)"
+    )]
+    source: Vec<source::LogSource>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = BuildPolicy::Keep,
+        help = "what happens to the other diagnostics when a new build starts"
+    )]
+    on_build: BuildPolicy,
+    #[arg(
+        short,
+        long,
+        help = "watch a JUnit XML or TAP report file, or a directory of *.xml/*.tap reports (repeatable)"
+    )]
+    report: Vec<String>,
+    #[arg(
+        short,
+        long,
+        help = "watch an LCOV tracefile and show never executed lines as hints (repeatable)"
+    )]
+    coverage: Vec<String>,
+    #[arg(
+        long,
+        help = "show per-function hit counts from --coverage as code lenses"
+    )]
+    coverage_lens: bool,
+    #[arg(
+        short,
+        long,
+        help = "watch a profiler report (perf report --sort srcline, perf annotate -l, gprof -l) and show its hottest lines (repeatable)"
+    )]
+    profile: Vec<String>,
+    #[arg(
+        long,
+        help = "number of profiler hotspots to show",
+        default_value_t = 10
+    )]
+    profile_top: usize,
+    #[arg(
+        long,
+        help = "diagnostics shown per file at most, repeated reports count once",
+        default_value_t = 200
+    )]
+    max_per_file: usize,
+    #[arg(
+        long,
+        help = "diagnostics shown in all files at most",
+        default_value_t = 2000
+    )]
+    max_total: usize,
+    #[arg(
+        long,
+        value_parser = transport::Listen::parse,
+        help = "wait for editors on tcp:HOST:PORT or unix:PATH instead of stdio, one after another"
+    )]
+    listen: Option<transport::Listen>,
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    #[command(
+        about = "run a command, show its output and append it to the run log (build log with --build)"
+    )]
+    Exec {
+        #[arg(short, long, help = "append to [basename]_build.log instead")]
+        build: bool,
+        #[arg(short, long, help = "name of the run [default: the command]")]
+        label: Option<String>,
+        #[arg(last = true, required = true)]
+        cmd: Vec<String>,
+    },
+    #[command(about = "print the log, socket and fifo paths of the workspace as shell variables")]
+    Paths,
+    #[command(
+        about = "watch and parse the logs once for every editor attached to the workspace, until the last one leaves"
+    )]
+    Daemon,
+    #[command(about = "connect stdio to the daemon of the workspace, starting it when needed")]
+    Attach,
 }
 static mut IS_VERBOSE: u8 = 0;
 
+// diagnostics read from one watched input
+#[derive(Default)]
+struct InputDiags {
+    // final, a log only adds to them until it is truncated
+    committed: HashMap<String, Vec<Value>>,
+    // from the unfinished end of a log, replaced by every read
+    provisional: HashMap<String, Vec<Value>>,
+}
+
 #[derive(Default)]
 struct DiagStore {
+    // uri -> diagnostics of all inputs as shown
     logs: HashMap<String, Vec<Value>>,
+    // uri -> diagnostics of all inputs with repeats folded, before the limits
+    folded: HashMap<String, Vec<Value>>,
+    // uri -> how many of them are shown
+    budgets: HashMap<String, usize>,
+    // input path -> its diagnostics
+    inputs: BTreeMap<String, InputDiags>,
     saved_uri: String,
     root_path: String,
+    config_paths: Vec<PathBuf>,
+    // the ones of them which exist, as last read
+    configs: Vec<(PathBuf, config::Config)>,
+    // ~/.config/clasangd/config.toml, read before the project's
+    user_config: Option<PathBuf>,
+    // where the diagnostics of each workspace are saved, none are without it
+    cache_dir: Option<PathBuf>,
+    user_parsers: Vec<config::UserParser>,
+    // rootPath and the workspace folders
+    roots: Vec<String>,
+    path_filter: path_filter::PathFilter,
+    code_lens: HashMap<String, Vec<Value>>,
+    coverage_lens: bool,
+    // the coverage lenses changed since the clients were asked to refresh them
+    lens_changed: bool,
+    // diagnostic sources switched off by clasangd.toggleSource
+    hidden_sources: HashSet<String>,
+    // the diagnostics of the last session were looked up on initialized
+    cache_restored: bool,
+    limits: aggregate::Limits,
+    // the directory the logs were named after when no workspace was given
+    named_after: Option<PathBuf>,
+}
+
+// a build starts when the build log is truncated or gets a run-start marker
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum BuildPolicy {
+    // keep the runtime errors of the last run
+    #[default]
+    Keep,
+    // drop the diagnostics of the run log
+    ClearRun,
+    // drop the diagnostics of every other log and stream
+    ClearAll,
+}
+
+// every file the watcher reads besides the config
+#[derive(Default, Clone)]
+struct Inputs {
+    // the build and run logs first
+    sources: Vec<source::LogSource>,
+    reports: Vec<String>,
+    coverage: Vec<String>,
+    profiles: Vec<String>,
+    profile_top: usize,
+    on_build: BuildPolicy,
 }
 
 impl DiagStore {
-    fn set_logs(&mut self, logs: HashMap<String, Vec<Value>>) {
-        self.logs = logs;
+    // returns the uris whose diagnostics changed
+    fn update_input(
+        &mut self,
+        input: &str,
+        committed: HashMap<String, Vec<Value>>,
+        provisional: HashMap<String, Vec<Value>>,
+        reset: bool,
+    ) -> HashSet<String> {
+        let entry = self.inputs.entry(input.to_string()).or_default();
+        let mut changed: HashSet<String> = entry.provisional.keys().cloned().collect();
+        // what is read again keeps the times it was first read
+        let mut before = std::mem::take(&mut entry.provisional);
+        if reset {
+            for (uri, list) in entry.committed.drain() {
+                changed.insert(uri.clone());
+                before.entry(uri).or_default().extend(list);
+            }
+        }
+        let now = aggregate::now();
+        let add = |list: &mut Vec<Value>, uri: &str, mut d: Value| {
+            if let Some(old) = before.get(uri) {
+                aggregate::keep_times(old, &mut d);
+            }
+            aggregate::add(list, d, now);
+        };
+        for (uri, diags) in committed {
+            changed.insert(uri.clone());
+            let list = entry.committed.entry(uri.clone()).or_default();
+            for d in diags {
+                add(list, &uri, d);
+            }
+        }
+        changed.extend(provisional.keys().cloned());
+        entry.provisional = provisional
+            .into_iter()
+            .map(|(uri, diags)| {
+                let mut list = Vec::new();
+                for d in diags {
+                    add(&mut list, &uri, d);
+                }
+                (uri, list)
+            })
+            .collect();
+
+        self.rebuild(changed)
+    }
+
+    // follows didChange edits of a document, None stands for a full replacement.
+    // returns the uris whose diagnostics changed
+    fn apply_edits(&mut self, uri: &str, edits: &[Option<edit::LineEdit>]) -> HashSet<String> {
+        let diags = self
+            .inputs
+            .values_mut()
+            .flat_map(|i| [i.committed.get_mut(uri), i.provisional.get_mut(uri)])
+            .flatten()
+            .flatten();
+        for d in diags {
+            let mut touched = false;
+            for e in edits {
+                touched |= match e {
+                    Some(e) => e.apply(&mut d["range"]),
+                    None => true,
+                };
+            }
+            if touched {
+                edit::mark_stale(d);
+            }
+        }
+        for lens in self.code_lens.get_mut(uri).into_iter().flatten() {
+            for e in edits.iter().flatten() {
+                e.apply(&mut lens["range"]);
+            }
+        }
+        self.rebuild(HashSet::from([uri.to_string()]))
+    }
+
+    // puts back the diagnostics of the last session, for inputs which have none yet
+    fn restore(&mut self, saved: BTreeMap<String, HashMap<String, Vec<Value>>>) -> HashSet<String> {
+        let mut changed = HashSet::new();
+        for (input, diags) in saved {
+            let has_own = self.inputs.get(&input).is_some_and(|i| {
+                i.committed
+                    .values()
+                    .chain(i.provisional.values())
+                    .any(|d| !d.is_empty())
+            });
+            if !has_own {
+                let key = format!("{}{}", cache::RESTORED, input);
+                changed.extend(self.update_input(&key, diags, HashMap::new(), true));
+            }
+        }
+        changed
+    }
+
+    // the input was read again, what was restored for it is outdated
+    fn drop_restored(&mut self, input: &str) -> HashSet<String> {
+        let Some(old) = self.inputs.remove(&format!("{}{}", cache::RESTORED, input)) else {
+            return HashSet::new();
+        };
+        self.rebuild(old.committed.into_keys().collect())
+    }
+
+    fn save_cache(&self) {
+        // until then the cache still holds the last session
+        if !self.cache_restored {
+            return;
+        }
+        if let Some(path) = self
+            .cache_dir
+            .as_deref()
+            .and_then(|d| cache::cache_path(d, &self.root_path))
+            && let Err(e) = cache::save(&path, &self.inputs)
+        {
+            eprintln!("[clasangd] failed to save diagnostics: {:#}", e);
+        }
+    }
+
+    // folds the diagnostics of all inputs for `uris`, then splits the limits among all files.
+    // returns the uris whose diagnostics changed, other files may get a new share of the total
+    fn rebuild(&mut self, uris: HashSet<String>) -> HashSet<String> {
+        let now = aggregate::now();
+        for uri in &uris {
+            let mut merged: Vec<Value> = Vec::new();
+            for d in self
+                .inputs
+                .values()
+                .flat_map(|i| {
+                    i.committed
+                        .get(uri)
+                        .into_iter()
+                        .chain(i.provisional.get(uri))
+                })
+                .flatten()
+            {
+                aggregate::add(&mut merged, d.clone(), now);
+            }
+            if merged.is_empty() {
+                self.folded.remove(uri);
+            } else {
+                self.folded.insert(uri.clone(), merged);
+            }
+        }
+        let counts: HashMap<String, usize> = self
+            .folded
+            .iter()
+            .map(|(uri, l)| (uri.clone(), aggregate::capped_len(l)))
+            .collect();
+        let budgets = aggregate::budgets(&counts, &self.limits);
+        let mut changed = uris;
+        changed.extend(
+            budgets
+                .iter()
+                .filter(|(uri, b)| self.budgets.get(*uri) != Some(*b))
+                .map(|(uri, _)| uri.clone()),
+        );
+        for uri in &changed {
+            let Some(folded) = self.folded.get(uri) else {
+                self.logs.remove(uri);
+                continue;
+            };
+            let mut shown = folded.clone();
+            let dropped = aggregate::cap(&mut shown, budgets[uri]);
+            if dropped > 0 {
+                shown.push(aggregate::summary(dropped, &self.limits));
+            }
+            self.logs.insert(uri.clone(), shown);
+        }
+        self.budgets = budgets;
+        changed
     }
 
     fn merged_for(&self, uri: &str) -> Vec<Value> {
@@ -65,8 +414,11 @@ impl DiagStore {
                     src: d["source"].as_str().unwrap_or("").to_string(),
                     msg: d["message"].as_str().unwrap_or("").to_string(),
                 };
+                if self.hidden_sources.contains(&key.src) {
+                    continue;
+                }
                 if seen.insert(key) {
-                    out.push(d.clone());
+                    out.push(aggregate::describe(d));
                 }
             }
         };
@@ -79,59 +431,229 @@ impl DiagStore {
 }
 
 type SharedStore = Arc<Mutex<DiagStore>>;
-type SharedClientWriter = Arc<Mutex<Stdout>>;
+type ClientWriter = Arc<Mutex<Box<dyn AsyncWrite + Send + Unpin>>>;
+type SharedClients = Arc<Mutex<Clients>>;
+
+// an editor connected to the server
+struct Client {
+    writer: ClientWriter,
+    // documents it has open, None gets the diagnostics of every file
+    open: Option<HashSet<String>>,
+    // it supports workspace/codeLens/refresh
+    lens_refresh: bool,
+}
+
+impl Client {
+    fn shows(&self, uri: &str) -> bool {
+        self.open.as_ref().is_none_or(|docs| docs.contains(uri))
+    }
+}
+
+#[derive(Default)]
+struct Clients {
+    next_id: u64,
+    list: HashMap<u64, Client>,
+    // id of the last request the server sent
+    last_request: u64,
+}
+
+impl Clients {
+    fn add(&mut self, writer: ClientWriter, open: Option<HashSet<String>>) -> u64 {
+        self.next_id += 1;
+        self.list.insert(
+            self.next_id,
+            Client {
+                writer,
+                open,
+                lens_refresh: false,
+            },
+        );
+        self.next_id
+    }
+
+    fn set_lens_refresh(&mut self, id: u64, refresh: bool) {
+        if let Some(c) = self.list.get_mut(&id) {
+            c.lens_refresh = refresh;
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // returns how many are left
+    fn remove(&mut self, id: u64) -> usize {
+        self.list.remove(&id);
+        self.list.len()
+    }
+
+    fn set_open(&mut self, id: u64, uri: &str, open: bool) {
+        if let Some(docs) = self.list.get_mut(&id).and_then(|c| c.open.as_mut()) {
+            if open {
+                docs.insert(uri.to_string());
+            } else {
+                docs.remove(uri);
+            }
+        }
+    }
+
+    fn shows(&self, id: u64, uri: &str) -> bool {
+        self.list.get(&id).is_some_and(|c| c.shows(uri))
+    }
+
+    // writers of the clients which want the diagnostics of `uri`
+    fn showing(&self, uri: &str) -> Vec<ClientWriter> {
+        self.list
+            .values()
+            .filter(|c| c.shows(uri))
+            .map(|c| c.writer.clone())
+            .collect()
+    }
+
+    // an id for a request to the clients, and the writers of those which refresh their lenses
+    fn refreshing_lenses(&mut self) -> (u64, Vec<ClientWriter>) {
+        self.last_request += 1;
+        let writers = self
+            .list
+            .values()
+            .filter(|c| c.lens_refresh)
+            .map(|c| c.writer.clone())
+            .collect();
+        (self.last_request, writers)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
     let is_verbose = args.verbose;
-    let file_name = args.name;
+    let mut named_after = None;
+    let file_name = match args.name {
+        Some(name) => name,
+        None => {
+            // the server names the logs after its workspace, clients look for a server's.
+            // the editor's root is only known after the logs are open, so it is the current directory
+            let cwd = std::env::current_dir()?;
+            let run_dir = runtime::runtime_dir(std::env::var_os("XDG_RUNTIME_DIR"));
+            let root = match (&args.workspace, &args.command) {
+                (Some(w), _) => w.clone(),
+                (None, Some(Cmd::Exec { .. } | Cmd::Paths)) => {
+                    runtime::find_workspace(&run_dir, &cwd)
+                }
+                (None, _) => {
+                    named_after = cwd.canonicalize().ok();
+                    cwd
+                }
+            };
+            runtime::workspace_name(&run_dir, &root)?
+        }
+    };
     let build_log = file_name.to_string() + "_build.log";
     let run_log = file_name.to_string() + "_run.log";
-    let store: SharedStore = Arc::new(Mutex::new(DiagStore::default()));
+    match args.command {
+        Some(Cmd::Exec { build, label, cmd }) => {
+            let log = if build { &build_log } else { &run_log };
+            let code = exec::exec(log, label.as_deref(), &cmd).await?;
+            std::process::exit(code);
+        }
+        Some(Cmd::Paths) => {
+            println!("CLASANGD_BUILD_LOG='{}'", build_log);
+            println!("CLASANGD_RUN_LOG='{}'", run_log);
+            println!("CLASANGD_SOCKET='{}.sock'", file_name);
+            println!("CLASANGD_FIFO='{}.fifo'", file_name);
+            return Ok(());
+        }
+        Some(Cmd::Attach) => {
+            daemon::attach(&file_name).await?;
+            std::process::exit(0);
+        }
+        Some(Cmd::Daemon) | None => {}
+    }
+    let is_daemon = matches!(args.command, Some(Cmd::Daemon));
+    let inputs = Inputs {
+        sources: [
+            source::LogSource::file(&build_log),
+            source::LogSource::file(&run_log),
+        ]
+        .into_iter()
+        .chain(args.source)
+        .collect(),
+        reports: args.report,
+        coverage: args.coverage,
+        profiles: args.profile,
+        profile_top: args.profile_top,
+        on_build: args.on_build,
+    };
+    let store: SharedStore = Arc::new(Mutex::new(DiagStore {
+        coverage_lens: args.coverage_lens,
+        limits: aggregate::Limits {
+            per_file: args.max_per_file,
+            total: args.max_total,
+        },
+        named_after,
+        user_config: config::user_config_path(),
+        cache_dir: cache::cache_dir(),
+        ..Default::default()
+    }));
     unsafe {
         IS_VERBOSE = is_verbose;
     }
 
-    if let Err(e) = File::create(build_log.clone()) {
-        eprintln!("[clasangd] failed to create {}  error: {:#}", &build_log, e);
-    } else {
-        unsafe {
-            if 0 < IS_VERBOSE {
-                eprintln!("[clasangd] succesed to create {}", &build_log);
-            }
+    unsafe {
+        if 0 < IS_VERBOSE {
+            eprintln!("[clasangd] logs: {}_{{build,run}}.log", file_name);
         }
     }
-    if let Err(e) = File::create(run_log.clone()) {
-        eprintln!("[clasangd] failed to create {}  error: {:#}", &run_log, e);
-    } else {
-        unsafe {
-            if 0 < IS_VERBOSE {
-                eprintln!("[clasangd] succesed to create {}", &run_log);
+    for log in [&build_log, &run_log] {
+        if let Err(e) = runtime::open_log(log, !args.keep_logs) {
+            eprintln!("[clasangd] failed to create {}  error: {:#}", log, e);
+        } else {
+            unsafe {
+                if 0 < IS_VERBOSE {
+                    eprintln!("[clasangd] succesed to create {}", log);
+                }
             }
         }
     }
-    let client_writer: SharedClientWriter = Arc::new(Mutex::new(io::stdout()));
-    let client_reader: BufReader<Stdin> = BufReader::new(io::stdin());
+    let clients: SharedClients = Arc::new(Mutex::new(Clients::default()));
+    // config files to watch are only known after initialize
+    let (config_tx, config_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<PathBuf>>();
 
-    let t1 = {
-        let build_log = build_log.clone();
-        let run_log = run_log.clone();
-        let client_writer = client_writer.clone();
+    // tools can also stream their output instead of writing the logs
+    for (path, fifo) in [
+        (file_name.to_string() + ".sock", false),
+        (file_name.to_string() + ".fifo", true),
+    ] {
         let store = store.clone();
+        let clients = clients.clone();
         tokio::spawn(async move {
-            let _ =
-                lsp_mainloop::detect_change_publish(build_log, run_log, client_writer, store).await;
-        })
-    };
-    let t2 = {
+            let path = PathBuf::from(path);
+            let res = if fifo {
+                ingest::listen_fifo(&path, store, clients).await
+            } else {
+                ingest::listen_socket(&path, store, clients).await
+            };
+            if let Err(e) = res {
+                eprintln!("[clasangd] {:#}", e);
+            }
+        });
+    }
+
+    {
+        let clients = clients.clone();
         let store = store.clone();
-        let client_writer = client_writer.clone();
         tokio::spawn(async move {
-            let _ = lsp_mainloop::client_to_server_loop(client_reader, client_writer, store).await;
-        })
-    };
+            let _ = lsp_mainloop::detect_change_publish(inputs, config_rx, clients, store).await;
+        });
+    }
 
-    let _ = tokio::join!(t2, t1);
-    Ok(())
+    if is_daemon {
+        daemon::serve(&daemon::socket_path(&file_name), store, clients, config_tx).await?;
+    } else if let Some(addr) = &args.listen {
+        transport::listen(addr, store, clients, config_tx).await?;
+    } else {
+        transport::session(io::stdin(), io::stdout(), None, store, clients, config_tx).await;
+    }
+    // exit, or the editor went away
+    std::process::exit(0);
 }
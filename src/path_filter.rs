@@ -0,0 +1,119 @@
+use crate::config::PathsConfig;
+use anyhow::{Context, Result};
+use glob::Pattern;
+use std::path::{Path, PathBuf};
+
+// directories inside a workspace which hold someone else's code
+const VENDORED: &[&str] = &[
+    "site-packages",
+    "node_modules",
+    "vendor",
+    "third_party",
+    ".venv",
+];
+
+// which files diagnostics may point at. exclude wins over include, include over just-my-code.
+// without just-my-code a non-empty include list is the only files allowed
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    just_my_code: bool,
+    // as given and canonicalized
+    roots: Vec<PathBuf>,
+}
+
+impl PathFilter {
+    pub fn new(rules: &PathsConfig, roots: &[String]) -> Result<Self> {
+        let compile = |globs: &[String]| {
+            globs
+                .iter()
+                .map(|g| Pattern::new(g).with_context(|| format!("invalid glob in paths {:?}", g)))
+                .collect::<Result<Vec<_>>>()
+        };
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        for root in roots.iter().filter(|r| !r.is_empty()) {
+            let root = PathBuf::from(root);
+            if let Ok(real) = root.canonicalize()
+                && real != root
+            {
+                dirs.push(real);
+            }
+            dirs.push(root);
+        }
+        Ok(PathFilter {
+            include: compile(&rules.include)?,
+            exclude: compile(&rules.exclude)?,
+            just_my_code: rules.just_my_code,
+            roots: dirs,
+        })
+    }
+
+    // nothing configured, the parsers keep the frames they always chose
+    pub fn is_active(&self) -> bool {
+        self.just_my_code || !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    // the path below the root it is in, if any
+    fn relative<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        self.roots.iter().find_map(|r| path.strip_prefix(r).ok())
+    }
+
+    pub fn in_workspace(&self, path: &Path) -> bool {
+        self.relative(path).is_some()
+    }
+
+    // relative globs like "build/**" match below a root
+    fn matches(&self, globs: &[Pattern], path: &Path) -> bool {
+        let rel = self.relative(path);
+        globs
+            .iter()
+            .any(|g| g.matches_path(path) || rel.is_some_and(|r| g.matches_path(r)))
+    }
+
+    pub fn allows(&self, path: &Path) -> bool {
+        if self.matches(&self.exclude, path) {
+            return false;
+        }
+        if self.matches(&self.include, path) {
+            return true;
+        }
+        if self.just_my_code {
+            return self.relative(path).is_some_and(|rel| {
+                !rel.components()
+                    .any(|c| VENDORED.iter().any(|v| c.as_os_str() == *v))
+            });
+        }
+        self.include.is_empty()
+    }
+
+    pub fn allows_uri(&self, uri: &str) -> bool {
+        uri.strip_prefix("file://")
+            .is_none_or(|p| self.allows(Path::new(p)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_rules_and_just_my_code() {
+        let rules = PathsConfig {
+            include: vec!["/opt/mylib/**".to_string()],
+            exclude: vec!["build/**".to_string()],
+            just_my_code: true,
+        };
+        let filter = PathFilter::new(&rules, &["/home/u/proj".to_string()]).unwrap();
+        assert!(filter.allows(Path::new("/home/u/proj/src/a.c")));
+        assert!(!filter.allows(Path::new("/home/u/proj/build/gen.c")));
+        assert!(!filter.allows(Path::new("/home/u/proj/.venv/lib/site-packages/x.py")));
+        assert!(!filter.allows(Path::new("/usr/include/c++/14/bits/stl_vector.h")));
+        assert!(filter.allows(Path::new("/opt/mylib/include/lib.h")));
+        assert!(!filter.allows(Path::new("ArrayList.java")));
+
+        let filter = PathFilter::new(&PathsConfig::default(), &[]).unwrap();
+        assert!(!filter.is_active());
+        assert!(filter.allows_uri("file:///usr/include/stdio.h"));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::log_parser::make_uri;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+pub fn parse_profile(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    top: usize,
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // perf report --stdio --sort srcline
+    // ex.
+    // # Overhead  Source:Line
+    //     45.67%  test.c:12
+    //     12.00%  c_test  test.c:15
+    //
+    // perf annotate --stdio -l  ... sorted summary
+    // ex.
+    //    32.05 test.c:12
+    //
+    // gprof -l  ... line-by-line flat profile
+    // ex.
+    //  38.46      0.05     0.05                             main (test.c:12 @ 401136)
+    let re_srcline = Regex::new(
+        r"^\s*(?P<pct>\d+(?:\.\d+)?)%?\s+(?:\S+\s+)*?(?P<file>[^\s:#]+):(?P<line>\d+)\s*$",
+    )
+    .expect("invalid regex");
+    let re_gprof = Regex::new(
+        r"^\s*(?P<pct>\d+\.\d+)\s+.*?(?P<func>\S+)\s+\((?P<file>[^\s:]+):(?P<line>\d+) @ [0-9a-fA-F]+\)\s*$",
+    )
+    .expect("invalid regex");
+
+    // (gprof or not, file, line) -> (percent, function). the rows of one report for the same
+    // line, ex. per command, add up. different reports each have their own 100%
+    type Hits = HashMap<(bool, String, u64), (f64, Option<String>)>;
+    let mut per_report: Hits = HashMap::new();
+    for line in text.lines() {
+        if line.trim_start().starts_with('#') {
+            continue;
+        }
+        let (cap, gprof) = match re_gprof.captures(line) {
+            Some(cap) => (cap, true),
+            None => match re_srcline.captures(line) {
+                Some(cap) => (cap, false),
+                None => continue,
+            },
+        };
+        let pct = cap["pct"].parse::<f64>().unwrap_or(0.0);
+        let line_num = cap["line"].parse::<u64>().unwrap_or(0);
+        if pct <= 0.0 || line_num == 0 {
+            continue;
+        }
+        let entry = per_report
+            .entry((gprof, cap["file"].to_string(), line_num))
+            .or_insert((0.0, None));
+        entry.0 += pct;
+        if let Some(func) = cap.name("func") {
+            entry.1 = Some(func.as_str().to_string());
+        }
+    }
+    // (file, line) -> the largest share of any report
+    let mut hits: HashMap<(String, u64), (f64, Option<String>)> = HashMap::new();
+    for ((_, file, line), (pct, func)) in per_report {
+        let entry = hits.entry((file, line)).or_insert((0.0, None));
+        entry.0 = entry.0.max(pct);
+        entry.1 = entry.1.take().or(func);
+    }
+
+    let mut hot: Vec<_> = hits.into_iter().collect();
+    hot.sort_by(|a, b| b.1.0.total_cmp(&a.1.0).then_with(|| a.0.cmp(&b.0)));
+    for ((file, line), (pct, func)) in hot.into_iter().take(top) {
+        let uri = make_uri(&file, saved_uri, root_path);
+        let line = line.saturating_sub(1);
+        let location = func.map(|f| format!(" in {}", f)).unwrap_or_default();
+        let diag = json!({
+            "range": {
+                "start": { "line": line, "character": 0 },
+                "end": { "line": line, "character": 1 }
+            },
+            "severity": 3,
+            "source": "profile",
+            "message": format!("hotspot: {:.2}% of samples{}", pct, location)
+        });
+        out.entry(uri).or_default().push(diag);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_top_hotspots() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file_path = temp_dir.path().join("test.c");
+        std::fs::write(&file_path, "int main(void) { return 0; }").unwrap();
+        let f = file_path.display();
+
+        let report = format!(
+            "# Overhead  Source:Line\n\
+             # ........  ...........\n\
+             #\n\
+             \x20   45.67%  {f}:12\n\
+             \x20   12.00%  c_test  {f}:15\n\
+             \x20    3.00%  sh      {f}:15\n\
+             \x20    1.00%  {f}:20\n\
+             \x20 38.46      0.05     0.05                             main ({f}:12 @ 401136)\n"
+        );
+        let mut out = HashMap::new();
+        parse_profile(
+            &report,
+            "file:///tmp/dummy.c",
+            temp_dir.path().to_str().unwrap(),
+            2,
+            &mut out,
+        );
+
+        let uri = format!("file://{}", file_path.canonicalize().unwrap().display());
+        let diags = out.get(&uri).expect("profile diagnostics missing");
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["severity"], json!(3));
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(11));
+        assert_eq!(
+            diags[0]["message"],
+            json!("hotspot: 45.67% of samples in main")
+        );
+        assert_eq!(diags[1]["range"]["start"]["line"], json!(14));
+        assert_eq!(diags[1]["message"], json!("hotspot: 15.00% of samples"));
+    }
+}
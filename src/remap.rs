@@ -0,0 +1,165 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, RwLock};
+
+// where a build tool may put the compilation database, relative to the workspace root
+pub const COMPILE_COMMANDS: &[&str] = &["compile_commands.json", "build/compile_commands.json"];
+
+// the rules of each workspace root, set from the config and the compilation database
+static RULES: LazyLock<RwLock<HashMap<String, Vec<Remap>>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+// a path prefix in the logs, ex. /src/app in a container, and where it is on this machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl Remap {
+    // `to` relative to the workspace root when not absolute
+    pub fn new(from: &str, to: &str, root: &str) -> Self {
+        Remap {
+            from: PathBuf::from(from),
+            to: Path::new(root).join(to),
+        }
+    }
+}
+
+// -fdebug-prefix-map=OLD=NEW writes NEW where the compiler saw OLD, so NEW is mapped back.
+// a relative OLD is relative to the directory of the entry
+fn prefix_maps(entry: &Value) -> Vec<Remap> {
+    let dir = Path::new(entry["directory"].as_str().unwrap_or_default());
+    let args: Vec<String> = match entry["arguments"].as_array() {
+        Some(args) => args
+            .iter()
+            .filter_map(|a| a.as_str())
+            .map(str::to_string)
+            .collect(),
+        None => entry["command"]
+            .as_str()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|a| a.trim_matches(['"', '\'']).to_string())
+            .collect(),
+    };
+    args.iter()
+        .filter_map(|a| {
+            a.strip_prefix("-fdebug-prefix-map=")
+                .or_else(|| a.strip_prefix("-ffile-prefix-map="))
+                .or_else(|| a.strip_prefix("-fmacro-prefix-map="))
+        })
+        .filter_map(|m| m.split_once('='))
+        .filter(|(_, new)| !new.is_empty())
+        .map(|(old, new)| Remap {
+            from: PathBuf::from(new),
+            to: dir.join(old),
+        })
+        .collect()
+}
+
+// the prefix maps of every entry of the workspace's compile_commands.json
+pub fn from_compile_commands(root: &str) -> Vec<Remap> {
+    let mut rules: Vec<Remap> = Vec::new();
+    for name in COMPILE_COMMANDS {
+        let path = Path::new(root).join(name);
+        let Ok(txt) = std::fs::read(&path) else {
+            continue;
+        };
+        let entries: Vec<Value> = match serde_json::from_slice(&txt) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("[clasangd] failed to parse {}: {:#}", path.display(), e);
+                continue;
+            }
+        };
+        for rule in entries.iter().flat_map(prefix_maps) {
+            if !rules.contains(&rule) {
+                rules.push(rule);
+            }
+        }
+    }
+    rules
+}
+
+// the longest prefix wins, among equal ones the first given. returns whether the rules changed
+pub fn set_rules(root: &str, mut rules: Vec<Remap>) -> bool {
+    rules.sort_by_key(|r| std::cmp::Reverse(r.from.components().count()));
+    let mut all = RULES.write().unwrap_or_else(|e| e.into_inner());
+    let old = if rules.is_empty() {
+        all.remove(root)
+    } else {
+        all.insert(root.to_string(), rules.clone())
+    };
+    old.unwrap_or_default() != rules
+}
+
+// `p` on this machine, if a rule of the root matches it
+pub fn apply(p: &str, root: &str) -> Option<String> {
+    let all = RULES.read().unwrap_or_else(|e| e.into_inner());
+    let path = Path::new(p);
+    all.get(root)?.iter().find_map(|r| {
+        path.strip_prefix(&r.from)
+            .ok()
+            .map(|rest| r.to.join(rest).to_string_lossy().into_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn remaps_configured_and_compiler_prefixes() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_str().unwrap();
+        std::fs::create_dir_all(temp_dir.path().join("build")).unwrap();
+        let commands = json!([
+            {
+                "directory": format!("{}/build", root),
+                "command": "cc -g -fdebug-prefix-map=..=/src/app -c ../main.c",
+                "file": "../main.c"
+            },
+            {
+                "directory": format!("{}/build", root),
+                "arguments": ["cc", "-ffile-prefix-map=/opt/vendor/lib=lib", "-c", "lib.c"],
+                "file": "lib.c"
+            }
+        ]);
+        std::fs::write(
+            temp_dir.path().join("build/compile_commands.json"),
+            commands.to_string(),
+        )
+        .unwrap();
+
+        let mut rules = vec![Remap::new("/proc/self/cwd", ".", root)];
+        rules.extend(from_compile_commands(root));
+        assert_eq!(rules.len(), 3);
+        assert!(set_rules(root, rules.clone()));
+        assert!(!set_rules(root, rules));
+
+        assert_eq!(
+            apply("/src/app/main.c", root),
+            Some(format!("{}/build/../main.c", root))
+        );
+        assert_eq!(
+            apply("/proc/self/cwd/src/a.c", root),
+            Some(format!("{}/./src/a.c", root))
+        );
+        assert_eq!(
+            apply("lib/x.h", root),
+            Some("/opt/vendor/lib/x.h".to_string())
+        );
+        assert_eq!(apply("/usr/include/stdio.h", root), None);
+
+        // the workspace's own lib/ isn't taken for the vendored one
+        std::fs::create_dir_all(temp_dir.path().join("lib")).unwrap();
+        std::fs::write(temp_dir.path().join("lib/x.h"), "").unwrap();
+        assert_eq!(
+            crate::file_index::resolve_path("lib/x.h", root),
+            Some(temp_dir.path().canonicalize().unwrap().join("lib/x.h"))
+        );
+    }
+}
@@ -0,0 +1,380 @@
+use crate::config::UserParser;
+use crate::log_parser::{make_uri, parse_diagnostics};
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// report files inside a watched directory
+pub fn is_report_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("xml" | "tap")
+    )
+}
+
+pub fn expand_report_paths(paths: &[String]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for p in paths {
+        let path = Path::new(p);
+        if path.is_dir() {
+            if let Ok(entries) = std::fs::read_dir(path) {
+                let mut found: Vec<PathBuf> = entries
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.is_file() && is_report_file(p))
+                    .collect();
+                found.sort();
+                files.extend(found);
+            }
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        }
+    }
+    files
+}
+
+pub fn parse_reports(
+    paths: &[String],
+    saved_uri: &str,
+    root_path: &str,
+    user_parsers: &[UserParser],
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    for file in expand_report_paths(paths) {
+        let Ok(txt) = std::fs::read_to_string(&file) else {
+            continue;
+        };
+        if txt.trim_start().starts_with('<') {
+            parse_junit(&txt, saved_uri, root_path, user_parsers, out);
+        } else {
+            parse_tap(&txt, saved_uri, root_path, user_parsers, out);
+        }
+    }
+}
+
+struct TestCase {
+    name: String,
+    file: Option<String>,
+    line: Option<u64>,
+    col: Option<u64>,
+}
+
+fn attr(e: &BytesStart, name: &[u8]) -> Option<String> {
+    e.try_get_attribute(name)
+        .ok()
+        .flatten()
+        .and_then(|a| a.unescape_value().ok().map(|v| v.into_owned()))
+}
+
+fn is_failure(e: &BytesStart) -> bool {
+    matches!(e.name().as_ref(), b"failure" | b"error")
+}
+
+fn failure_message(e: &BytesStart) -> String {
+    attr(e, b"message")
+        .or_else(|| attr(e, b"type"))
+        .unwrap_or_default()
+}
+
+pub fn parse_junit(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    user_parsers: &[UserParser],
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // ex.
+    // <testsuite name="pytest" tests="2" failures="1">
+    //   <testcase classname="tests.test_a" name="test_x" file="tests/test_a.py" line="3">
+    //     <failure message="assert 1 == 2">tests/test_a.py:4: AssertionError</failure>
+    //   </testcase>
+    // </testsuite>
+    let mut reader = Reader::from_str(text);
+    let mut suite_file: Option<String> = None;
+    let mut case: Option<TestCase> = None;
+    // (failure message, body)
+    let mut failure: Option<(String, String)> = None;
+
+    loop {
+        let event = match reader.read_event() {
+            Ok(Event::Eof) => break,
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("[clasangd] broken junit report: {:#}", e);
+                break;
+            }
+        };
+        match event {
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"testsuite" => {
+                suite_file = attr(&e, b"file").or(suite_file);
+            }
+            Event::Start(e) if e.name().as_ref() == b"testcase" => {
+                let name = match (attr(&e, b"classname"), attr(&e, b"name")) {
+                    (Some(class), Some(name)) if !class.is_empty() => format!("{class}.{name}"),
+                    (_, Some(name)) => name,
+                    (class, None) => class.unwrap_or_default(),
+                };
+                case = Some(TestCase {
+                    name,
+                    file: attr(&e, b"file").or_else(|| suite_file.clone()),
+                    line: attr(&e, b"line").and_then(|l| l.trim().parse().ok()),
+                    col: None,
+                });
+            }
+            Event::Start(e) if case.is_some() && is_failure(&e) => {
+                failure = Some((failure_message(&e), String::new()));
+            }
+            Event::Empty(e) if is_failure(&e) => {
+                if let Some(c) = case.as_ref() {
+                    let message = failure_message(&e);
+                    push_failure(
+                        c,
+                        "junit",
+                        &message,
+                        "",
+                        saved_uri,
+                        root_path,
+                        user_parsers,
+                        out,
+                    );
+                }
+            }
+            Event::Text(t) => {
+                if let Some((_, body)) = failure.as_mut() {
+                    body.push_str(&t.unescape().unwrap_or_default());
+                }
+            }
+            Event::CData(t) => {
+                if let Some((_, body)) = failure.as_mut() {
+                    body.push_str(&String::from_utf8_lossy(&t));
+                }
+            }
+            Event::End(e) if matches!(e.name().as_ref(), b"failure" | b"error") => {
+                if let (Some(c), Some((message, body))) = (case.as_ref(), failure.take()) {
+                    push_failure(
+                        c,
+                        "junit",
+                        &message,
+                        &body,
+                        saved_uri,
+                        root_path,
+                        user_parsers,
+                        out,
+                    );
+                }
+            }
+            Event::End(e) if e.name().as_ref() == b"testcase" => {
+                case = None;
+            }
+            Event::End(e) if e.name().as_ref() == b"testsuite" => {
+                suite_file = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn push_failure(
+    case: &TestCase,
+    source: &str,
+    message: &str,
+    body: &str,
+    saved_uri: &str,
+    root_path: &str,
+    user_parsers: &[UserParser],
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    let message = if message.trim().is_empty() {
+        body.lines()
+            .find(|l| !l.trim().is_empty())
+            .unwrap_or("failed")
+    } else {
+        message
+    };
+    if let (Some(file), Some(line)) = (&case.file, case.line) {
+        let uri = make_uri(file, saved_uri, root_path);
+        let line = line.saturating_sub(1);
+        let col = case.col.unwrap_or(1).saturating_sub(1);
+        let diag = json!({
+            "range": {
+                "start": { "line": line, "character": col },
+                "end": { "line": line, "character": col + 1 }
+            },
+            "severity": 1,
+            "source": source,
+            "message": format!("{}: {}", case.name, message.trim())
+        });
+        out.entry(uri).or_default().push(diag);
+        return;
+    }
+    // no location attribute, the stack trace in the body has it
+    for (uri, diags) in parse_diagnostics(body, saved_uri, root_path, user_parsers) {
+        let entry = out.entry(uri).or_default();
+        for mut diag in diags {
+            let msg = diag["message"].as_str().unwrap_or_default().to_string();
+            diag["message"] = json!(format!("{}: {}", case.name, msg));
+            entry.push(diag);
+        }
+    }
+}
+
+pub fn parse_tap(
+    text: &str,
+    saved_uri: &str,
+    root_path: &str,
+    user_parsers: &[UserParser],
+    out: &mut HashMap<String, Vec<Value>>,
+) {
+    // ex.
+    // TAP version 13
+    // 1..2
+    // ok 1 - adds numbers
+    // not ok 2 - subtracts numbers
+    //   ---
+    //   message: 'expected 1 got 2'
+    //   at:
+    //     file: test/math.js
+    //     line: 12
+    //     column: 5
+    //   ...
+    let re_not_ok =
+        Regex::new(r"^\s*not ok\b\s*\d*\s*(?:-\s*)?([^#]*)(#.*)?$").expect("invalid regex");
+    let re_key = Regex::new(r#"^\s*(message|file|line|column|at|stack):\s*['"]?(.*?)['"]?\s*$"#)
+        .expect("invalid regex");
+    let re_at = Regex::new(r"\(?([^\s()]+):(\d+)(?::(\d+))?\)?\s*$").expect("invalid regex");
+
+    let lines: Vec<&str> = text.lines().collect();
+    for (i, line) in lines.iter().enumerate() {
+        let Some(cap) = re_not_ok.captures(line) else {
+            continue;
+        };
+        let directive = cap.get(2).map(|m| m.as_str().to_ascii_uppercase());
+        if directive.is_some_and(|d| d.contains("TODO") || d.contains("SKIP")) {
+            continue;
+        }
+        let name = cap[1].trim().to_string();
+
+        // YAML diagnostic block
+        let mut body = String::new();
+        let (mut message, mut file, mut line_num, mut col) = (None, None, None, None);
+        if lines.get(i + 1).is_some_and(|l| l.trim() == "---") {
+            for l in lines.iter().skip(i + 2).take_while(|l| l.trim() != "...") {
+                body.push_str(l);
+                body.push('\n');
+                let Some(kv) = re_key.captures(l) else {
+                    continue;
+                };
+                let value = kv[2].to_string();
+                match &kv[1] {
+                    "message" => message = Some(value),
+                    "file" => file = Some(value),
+                    "line" => line_num = value.parse::<u64>().ok(),
+                    "column" => col = value.parse::<u64>().ok(),
+                    "at" => {
+                        if let Some(at) = re_at.captures(&value) {
+                            file = Some(at[1].to_string());
+                            line_num = at[2].parse::<u64>().ok();
+                            col = at.get(3).and_then(|c| c.as_str().parse::<u64>().ok());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let case = TestCase {
+            name,
+            file,
+            line: line_num,
+            col,
+        };
+        let message = message.unwrap_or_default();
+        push_failure(
+            &case,
+            "tap",
+            &message,
+            &body,
+            saved_uri,
+            root_path,
+            user_parsers,
+            out,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_junit_and_tap_reports() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let py = temp_dir.path().join("test_a.py");
+        let java = temp_dir.path().join("CalcTest.java");
+        std::fs::write(&py, "# python test file").unwrap();
+        std::fs::write(&java, "// java test file").unwrap();
+
+        let junit = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<testsuites>
+  <testsuite name="pytest">
+    <testcase classname="test_a" name="test_x" file="{py}" line="3">
+      <failure message="assert 1 == 2">body</failure>
+    </testcase>
+    <testcase classname="test_a" name="test_ok" file="{py}" line="9"/>
+  </testsuite>
+  <testsuite name="CalcTest">
+    <testcase classname="CalcTest" name="adds">
+      <failure type="java.lang.AssertionError"><![CDATA[java.lang.AssertionError: expected:<3> but was:<4>
+	at CalcTest.adds(CalcTest.java:7)
+]]></failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#,
+            py = py.display()
+        );
+        let tap = format!(
+            "TAP version 13\n1..3\nok 1 - first\nnot ok 2 - second\n  ---\n  message: 'expected 1 got 2'\n  at:\n    file: {py}\n    line: 12\n    column: 5\n  ...\nnot ok 3 - later # TODO not done\n",
+            py = py.display()
+        );
+        std::fs::write(temp_dir.path().join("junit.xml"), junit).unwrap();
+        std::fs::write(temp_dir.path().join("results.tap"), tap).unwrap();
+
+        let mut out = HashMap::new();
+        let root = temp_dir.path().to_str().unwrap();
+        parse_reports(
+            &[root.to_string()],
+            "file:///tmp/dummy",
+            root,
+            &[],
+            &mut out,
+        );
+
+        let py_uri = format!("file://{}", py.canonicalize().unwrap().display());
+        let diags = out
+            .get(&py_uri)
+            .expect("diagnostic missing for python test");
+        assert_eq!(diags.len(), 2);
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(2));
+        assert_eq!(diags[0]["message"], json!("test_a.test_x: assert 1 == 2"));
+        assert_eq!(diags[1]["source"], json!("tap"));
+        assert_eq!(diags[1]["range"]["start"]["line"], json!(11));
+        assert_eq!(diags[1]["range"]["start"]["character"], json!(4));
+        assert_eq!(diags[1]["message"], json!("second: expected 1 got 2"));
+
+        let java_uri = format!("file://{}", java.canonicalize().unwrap().display());
+        let diags = out
+            .get(&java_uri)
+            .expect("diagnostic missing for java test");
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0]["range"]["start"]["line"], json!(6));
+        assert_eq!(
+            diags[0]["message"],
+            json!("CalcTest.adds: expected:<3> but was:<4>")
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use crate::cache;
+use anyhow::{Context, Result, bail};
+use std::ffi::OsString;
+use std::fs::{DirBuilder, File, OpenOptions};
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::UnixListener;
+
+// $XDG_RUNTIME_DIR/clasangd, or /tmp/clasangd-<uid> where there is no runtime dir
+pub fn runtime_dir(xdg_runtime_dir: Option<OsString>) -> PathBuf {
+    match xdg_runtime_dir.filter(|v| !v.is_empty()) {
+        Some(dir) => Path::new(&dir).join("clasangd"),
+        None => std::env::temp_dir().join(format!("clasangd-{}", unsafe { libc::getuid() })),
+    }
+}
+
+// a directory only we can use, anything else at its path is refused
+fn private_dir(dir: &Path) -> Result<()> {
+    if let Err(e) = DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+        bail!("failed to create {}: {}", dir.display(), e);
+    }
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.uid() != unsafe { libc::getuid() } {
+        bail!(
+            "{} is not a directory owned by the current user",
+            dir.display()
+        );
+    }
+    if meta.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+// <runtime dir>/<root dir name>-<hash of the root>
+fn workspace_dir(run_dir: &Path, root: &Path) -> PathBuf {
+    let base = root
+        .file_name()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "root".to_string());
+    run_dir.join(format!(
+        "{}-{}",
+        base,
+        &cache::hash(root.to_string_lossy().as_bytes())[..8]
+    ))
+}
+
+// the log basename of a workspace, its directory is created
+pub fn workspace_name(run_dir: &Path, root: &Path) -> Result<String> {
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("no workspace at {}", root.display()))?;
+    private_dir(run_dir)?;
+    let dir = workspace_dir(run_dir, &root);
+    private_dir(&dir)?;
+    Ok(dir.join("clasangd").to_string_lossy().into_owned())
+}
+
+// the workspace of a server already running for `dir` or one of its parents,
+// so that commands run from a subdirectory find its logs
+pub fn find_workspace(run_dir: &Path, dir: &Path) -> PathBuf {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.ancestors()
+        .find(|d| workspace_dir(run_dir, d).is_dir())
+        .unwrap_or(&dir)
+        .to_path_buf()
+}
+
+// refuses to follow a symlink someone put at the path of a log
+pub fn open_log(path: &str, truncate: bool) -> std::io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(truncate)
+        .append(!truncate)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+// a socket left by a previous server is replaced, one a server still listens on
+// and anything else at the path are refused
+pub fn bind_socket(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => bail!("another server is listening on {}", path.display()),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to check {}", path.display()));
+            }
+        }
+    }
+    let listener =
+        UnixListener::bind(path).with_context(|| format!("failed to bind {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_workspaces_and_refuses_symlinks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().join("project");
+        std::fs::create_dir_all(root.join("src")).unwrap();
+        let run_dir = runtime_dir(Some(temp_dir.path().join("run").into()));
+
+        let name = workspace_name(&run_dir, &root).unwrap();
+        assert!(name.contains("/clasangd/project-"), "{}", name);
+        let dir = Path::new(&name).parent().unwrap();
+        assert_eq!(std::fs::metadata(dir).unwrap().mode() & 0o777, 0o700);
+        assert_eq!(
+            find_workspace(&run_dir, &root.join("src")),
+            root.canonicalize().unwrap()
+        );
+
+        let log = format!("{}_run.log", name);
+        open_log(&log, true).unwrap();
+        assert_eq!(std::fs::metadata(&log).unwrap().mode() & 0o777, 0o600);
+
+        let target = temp_dir.path().join("target");
+        let link = format!("{}_build.log", name);
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(open_log(&link, true).is_err());
+        assert!(!target.exists());
+    }
+
+    #[tokio::test]
+    async fn replaces_only_stale_sockets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("clasangd.sock");
+        let listener = bind_socket(&path).unwrap();
+        assert!(bind_socket(&path).is_err());
+        drop(listener);
+        // the socket file of a server which is gone
+        assert!(path.exists());
+        bind_socket(&path).unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+use crate::config;
+use crate::log_parser;
+use anyhow::{Context, Result, bail};
+use glob::Pattern;
+use notify::RecursiveMode;
+use std::path::{Path, PathBuf};
+
+// a watched log, a glob of logs or a directory of logs
+// spec: [LABEL=]PATH[:PARSER,...]
+// a path which exists is never split, the parsers are taken only when they are known
+// ex. /tmp/clasangd_run.log
+//     server=/var/log/app/*.log:sanitizer,stacktrace
+//     tests=build/test-logs
+#[derive(Debug, Clone)]
+pub struct LogSource {
+    pub pattern: String,
+    // empty runs every parser
+    pub parsers: Vec<String>,
+    // replaces the source of its diagnostics
+    pub label: Option<String>,
+}
+
+fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+// against the current directory, the watcher reports "app.log" in "." as "./app.log"
+pub fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// the directory to watch for a file, so that it is seen when created, removed or replaced
+pub fn watch_dir(path: &Path) -> PathBuf {
+    let path = absolute(path);
+    if path.is_dir() {
+        return path;
+    }
+    match path.parent() {
+        Some(dir) => dir.to_path_buf(),
+        None => path,
+    }
+}
+
+impl LogSource {
+    pub fn file(path: &str) -> Self {
+        LogSource {
+            pattern: path.to_string(),
+            parsers: Vec::new(),
+            label: None,
+        }
+    }
+
+    // the built-in parsers and those of the config files seen from the current directory
+    pub fn parse(spec: &str) -> Result<Self> {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut known: Vec<String> = log_parser::BUILTIN_PARSERS
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        let user_config = config::user_config_path();
+        for path in config::config_paths(user_config.as_deref(), &cwd.to_string_lossy()) {
+            if let Ok(cfg) = config::load_config(&path) {
+                known.extend(cfg.parsers.into_iter().map(|p| p.name));
+            }
+        }
+        Self::parse_in(spec, &cwd, &known)
+    }
+
+    // ex. "foo:bar.log" is a path, so is "a=b.log" when it exists in `dir`
+    fn parse_in(spec: &str, dir: &Path, known: &[String]) -> Result<Self> {
+        let (rest, parsers) = match spec.rsplit_once(':') {
+            Some((rest, list)) => {
+                let names: Vec<String> = list
+                    .split(',')
+                    .map(|p| p.trim().to_string())
+                    .filter(|p| !p.is_empty())
+                    .collect();
+                if !names.is_empty() && names.iter().all(|n| known.contains(n)) {
+                    (rest, names)
+                } else {
+                    (spec, Vec::new())
+                }
+            }
+            None => (spec, Vec::new()),
+        };
+        let (label, pattern) = match rest.split_once('=') {
+            Some((label, pattern)) if !label.contains('/') && !dir.join(rest).exists() => {
+                (Some(label.to_string()), pattern)
+            }
+            _ => (None, rest),
+        };
+        if pattern.is_empty() {
+            bail!("no path in source {:?}", spec);
+        }
+        if is_glob(pattern) {
+            Pattern::new(pattern).with_context(|| format!("invalid glob in source {:?}", spec))?;
+        }
+        Ok(LogSource {
+            pattern: pattern.to_string(),
+            parsers,
+            label: label.filter(|l| !l.is_empty()),
+        })
+    }
+
+    // the files this source currently stands for
+    pub fn expand(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = if is_glob(&self.pattern) {
+            glob::glob(&self.pattern)
+                .map(|paths| paths.flatten().filter(|p| p.is_file()).collect())
+                .unwrap_or_default()
+        } else if Path::new(&self.pattern).is_dir() {
+            std::fs::read_dir(&self.pattern)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|e| e.path())
+                        .filter(|p| p.is_file())
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            vec![PathBuf::from(&self.pattern)]
+        };
+        files.sort();
+        files
+    }
+
+    // whether a file created or removed at `path` belongs to this source
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = absolute(path);
+        let own = absolute(Path::new(&self.pattern));
+        if is_glob(&self.pattern) {
+            Pattern::new(&own.to_string_lossy()).is_ok_and(|p| p.matches_path(&path))
+        } else {
+            path == own || path.parent() == Some(&own)
+        }
+    }
+
+    // what to hand to the watcher so that new files show up as well
+    pub fn watch_target(&self) -> (PathBuf, RecursiveMode) {
+        if !is_glob(&self.pattern) {
+            return (
+                watch_dir(Path::new(&self.pattern)),
+                RecursiveMode::NonRecursive,
+            );
+        }
+        // the literal directories in front of the first wildcard
+        let path = absolute(Path::new(&self.pattern));
+        let mut dir = PathBuf::new();
+        let mut components = path.components().peekable();
+        while let Some(c) = components.next() {
+            if is_glob(&c.as_os_str().to_string_lossy()) {
+                let mode = if components.peek().is_some() {
+                    RecursiveMode::Recursive
+                } else {
+                    RecursiveMode::NonRecursive
+                };
+                return (dir, mode);
+            }
+            dir.push(c);
+        }
+        (dir, RecursiveMode::NonRecursive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_specs_and_expands_globs() {
+        let src = LogSource::parse("server=/var/log/app/*.log:sanitizer, stacktrace").unwrap();
+        assert_eq!(src.label.as_deref(), Some("server"));
+        assert_eq!(src.pattern, "/var/log/app/*.log");
+        assert_eq!(src.parsers, ["sanitizer", "stacktrace"]);
+        assert_eq!(
+            src.watch_target(),
+            (PathBuf::from("/var/log/app"), RecursiveMode::NonRecursive)
+        );
+
+        let src = LogSource::parse("/tmp/a=b/run.log").unwrap();
+        assert!(src.label.is_none());
+        assert_eq!(src.pattern, "/tmp/a=b/run.log");
+        assert!(src.parsers.is_empty());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        for name in ["a.log", "b.log", "c.txt"] {
+            std::fs::write(temp_dir.path().join(name), "").unwrap();
+        }
+        let dir = temp_dir.path().to_str().unwrap();
+        let src = LogSource::parse(&format!("{}/*.log", dir)).unwrap();
+        assert_eq!(
+            src.expand(),
+            [temp_dir.path().join("a.log"), temp_dir.path().join("b.log")]
+        );
+        assert_eq!(LogSource::parse(dir).unwrap().expand().len(), 3);
+        assert!(LogSource::parse("label=").is_err());
+
+        // "=" and ":" in file names
+        let known = ["sanitizer".to_string()];
+        std::fs::write(temp_dir.path().join("a=b.log"), "").unwrap();
+        let src = LogSource::parse_in("a=b.log:sanitizer", temp_dir.path(), &known).unwrap();
+        assert!(src.label.is_none());
+        assert_eq!(src.pattern, "a=b.log");
+        assert_eq!(src.parsers, ["sanitizer"]);
+        let src = LogSource::parse_in("a=c.log", temp_dir.path(), &known).unwrap();
+        assert_eq!(src.label.as_deref(), Some("a"));
+        assert_eq!(src.pattern, "c.log");
+        let src = LogSource::parse_in("foo:bar.log", temp_dir.path(), &known).unwrap();
+        assert_eq!(src.pattern, "foo:bar.log");
+        assert!(src.parsers.is_empty());
+        let src = LogSource::parse_in("foo:bar.log:mytool", temp_dir.path(), &known).unwrap();
+        assert_eq!(src.pattern, "foo:bar.log:mytool");
+    }
+
+    #[test]
+    fn matches_relative_logs() {
+        let cwd = std::env::current_dir().unwrap();
+        let src = LogSource::parse("app.log").unwrap();
+        assert_eq!(
+            src.watch_target(),
+            (cwd.clone(), RecursiveMode::NonRecursive)
+        );
+        assert!(src.matches(Path::new("./app.log")));
+        assert!(src.matches(&cwd.join("app.log")));
+        assert!(!src.matches(&cwd.join("other.log")));
+
+        let src = LogSource::parse("logs/*.log").unwrap();
+        assert_eq!(
+            src.watch_target(),
+            (cwd.join("logs"), RecursiveMode::NonRecursive)
+        );
+        assert!(src.matches(&cwd.join("logs/a.log")));
+        assert!(src.matches(Path::new("./logs/a.log")));
+    }
+}
@@ -0,0 +1,91 @@
+use addr2line::Loader;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+// the modules loaded for every log read so far, a module is loaded again once it was rebuilt
+static SYMBOLIZER: LazyLock<Mutex<Symbolizer>> =
+    LazyLock::new(|| Mutex::new(Symbolizer::default()));
+
+// resolves "(module+0xoffset)" frames with the DWARF line table of the module,
+// for logs written without llvm-symbolizer in PATH
+#[derive(Default)]
+pub struct Symbolizer {
+    // module -> (its modification time, the loader or None when it is missing or has no debug info)
+    loaders: HashMap<String, (Option<SystemTime>, Option<Loader>)>,
+}
+
+// `return_address` for the frames above #0, they point after the call
+pub fn locate(module: &str, offset: u64, return_address: bool) -> Option<(String, u64, u64)> {
+    let mut symbolizer = SYMBOLIZER.lock().unwrap_or_else(|e| e.into_inner());
+    let offset = if return_address {
+        offset.saturating_sub(1)
+    } else {
+        offset
+    };
+    symbolizer.locate(module, offset)
+}
+
+impl Symbolizer {
+    // (file, line, column) 1-based, column is 1 when the line table has none
+    pub fn locate(&mut self, module: &str, offset: u64) -> Option<(String, u64, u64)> {
+        let modified = std::fs::metadata(module).and_then(|m| m.modified()).ok();
+        if self.loaders.get(module).is_none_or(|(m, _)| *m != modified) {
+            let loader = match Loader::new(module) {
+                Ok(l) => Some(l),
+                Err(e) => {
+                    eprintln!("[clasangd] failed to load debug info of {}: {}", module, e);
+                    None
+                }
+            };
+            self.loaders.insert(module.to_string(), (modified, loader));
+        }
+        let loader = self.loaders.get(module)?.1.as_ref()?;
+        let probe = offset + loader.relative_address_base();
+        let loc = loader.find_location(probe).ok()??;
+        let file = loc.file?.to_string();
+        let line = loc.line.filter(|l| *l != 0)? as u64;
+        let col = loc.column.filter(|c| *c != 0).unwrap_or(1) as u64;
+        Some((file, line, col))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[inline(never)]
+    fn probe_target() -> u64 {
+        std::hint::black_box(42)
+    }
+
+    #[test]
+    fn locates_own_function() {
+        // the test binary is PIE, so offset = runtime address - load base
+        let exe = std::env::current_exe().unwrap();
+        let exe = exe.to_str().unwrap();
+        let maps = std::fs::read_to_string("/proc/self/maps").unwrap();
+        let base = maps
+            .lines()
+            .filter(|l| l.ends_with(exe))
+            .find(|l| l.split_whitespace().nth(2) == Some("00000000"))
+            .and_then(|l| l.split('-').next())
+            .and_then(|b| u64::from_str_radix(b, 16).ok())
+            .expect("load base of the test binary");
+        let addr = probe_target as *const () as u64;
+        assert_eq!(probe_target(), 42);
+
+        let mut symbolizer = Symbolizer::default();
+        let (file, line, _) = symbolizer
+            .locate(exe, addr - base)
+            .expect("no location for probe_target");
+        assert!(file.ends_with("symbolize.rs"), "{}", file);
+        assert!(line > 0);
+        // a return address is looked up one byte before it
+        assert_eq!(
+            locate(exe, addr - base + 1, true),
+            symbolizer.locate(exe, addr - base)
+        );
+        assert!(symbolizer.locate("/nonexistent/binary", 0).is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::ClientWriter;
+use crate::IS_VERBOSE;
+use crate::SharedClients;
+use crate::SharedStore;
+use crate::lsp_mainloop;
+use crate::runtime;
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::UnboundedSender;
+
+// where the server waits for its editor instead of stdio
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listen {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Listen {
+    // ex. tcp:127.0.0.1:7000, unix:/tmp/clasangd.lsp.sock
+    pub fn parse(spec: &str) -> Result<Self> {
+        match spec.split_once(':') {
+            Some(("tcp", addr)) if addr.contains(':') => Ok(Listen::Tcp(addr.to_string())),
+            Some(("unix", path)) if !path.is_empty() => Ok(Listen::Unix(PathBuf::from(path))),
+            _ => bail!("expected tcp:HOST:PORT or unix:PATH, got {:?}", spec),
+        }
+    }
+}
+
+// one LSP session on a connection, returns how many clients are left after it.
+// `open` None sends the client the diagnostics of every file
+pub async fn session<R, W>(
+    reader: R,
+    writer: W,
+    open: Option<HashSet<String>>,
+    store: SharedStore,
+    clients: SharedClients,
+    config_tx: UnboundedSender<Vec<PathBuf>>,
+) -> usize
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Send + Unpin + 'static,
+{
+    let writer: ClientWriter = Arc::new(Mutex::new(Box::new(writer)));
+    let id = clients.lock().await.add(writer.clone(), open);
+    unsafe {
+        if 0 < IS_VERBOSE {
+            eprintln!("[clasangd] client {} attached", id);
+        }
+    }
+    lsp_mainloop::client_to_server_loop(
+        BufReader::new(reader),
+        writer,
+        id,
+        clients.clone(),
+        config_tx,
+        store,
+    )
+    .await;
+    let left = clients.lock().await.remove(id);
+    unsafe {
+        if 0 < IS_VERBOSE {
+            eprintln!("[clasangd] client {} left, {} remaining", id, left);
+        }
+    }
+    left
+}
+
+// serves one editor after another, the server keeps running between them
+pub async fn listen(
+    addr: &Listen,
+    store: SharedStore,
+    clients: SharedClients,
+    config_tx: UnboundedSender<Vec<PathBuf>>,
+) -> Result<()> {
+    match addr {
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("failed to bind {}", addr))?;
+            eprintln!("[clasangd] listening on tcp:{}", listener.local_addr()?);
+            loop {
+                let (conn, _) = listener.accept().await?;
+                let (reader, writer) = conn.into_split();
+                let (store, clients, config_tx) =
+                    (store.clone(), clients.clone(), config_tx.clone());
+                session(reader, writer, None, store, clients, config_tx).await;
+            }
+        }
+        Listen::Unix(path) => {
+            let listener = runtime::bind_socket(path)?;
+            eprintln!("[clasangd] listening on unix:{}", path.display());
+            loop {
+                let (conn, _) = listener.accept().await?;
+                let (reader, writer) = conn.into_split();
+                let (store, clients, config_tx) =
+                    (store.clone(), clients.clone(), config_tx.clone());
+                session(reader, writer, None, store, clients, config_tx).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_listen_addresses() {
+        assert_eq!(
+            Listen::parse("tcp:127.0.0.1:7000").unwrap(),
+            Listen::Tcp("127.0.0.1:7000".to_string())
+        );
+        assert_eq!(
+            Listen::parse("unix:/tmp/clasangd.lsp.sock").unwrap(),
+            Listen::Unix(PathBuf::from("/tmp/clasangd.lsp.sock"))
+        );
+        assert!(Listen::parse("tcp:7000").is_err());
+        assert!(Listen::parse("/tmp/clasangd.lsp.sock").is_err());
+    }
+
+    #[tokio::test]
+    async fn session_without_cache_file() {
+        use crate::lsp_io::write_lsp_message;
+        use serde_json::json;
+
+        // a new root has no cache file yet
+        let temp_dir = tempfile::tempdir().unwrap();
+        let root = temp_dir.path().to_str().unwrap();
+        let (mut editor, server) = tokio::io::duplex(1 << 16);
+        let (reader, writer) = tokio::io::split(server);
+        let store = SharedStore::default();
+        // nothing is read from or written to the user's config and cache
+        {
+            let mut st = store.lock().await;
+            st.user_config = Some(temp_dir.path().join("config.toml"));
+            st.cache_dir = Some(temp_dir.path().join("cache"));
+        }
+        let clients = SharedClients::default();
+        let (config_tx, _config_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        for msg in [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"rootPath": root}}),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+                "textDocument": {"uri": format!("file://{}/a.c", root), "text": ""}
+            }}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ] {
+            write_lsp_message(&mut editor, &msg).await.unwrap();
+        }
+        let left = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            session(reader, writer, None, store.clone(), clients, config_tx),
+        )
+        .await
+        .expect("session hung");
+        assert_eq!(left, 0);
+        assert!(store.lock().await.cache_restored);
+        let saved = crate::cache::cache_path(&temp_dir.path().join("cache"), root).unwrap();
+        assert!(saved.is_file());
+    }
+}